@@ -0,0 +1,193 @@
+use crate::quad::Side;
+use crate::voxel::BlockType;
+
+use serde::Deserialize;
+use std::path::Path;
+use std::sync::OnceLock;
+
+const DEFAULT_BLOCKS: &str = include_str!("../blocks.toml");
+
+static REGISTRY: OnceLock<BlockRegistry> = OnceLock::new();
+
+// unknown keys are errors, a misspelt or outdated property would otherwise silently fall back to its default
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BlockFile {
+    block: Vec<BlockConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FaceConfig {
+    top: Option<[u8; 4]>,
+    side: Option<[u8; 4]>,
+    bottom: Option<[u8; 4]>,
+    submerged: Option<[u8; 4]>,
+}
+
+// full blocks hide their neighbours' faces, cutout blocks keep faces between each other,
+// translucent blocks are drawn after the opaque mesh and merge with their own kind
+#[derive(Deserialize, Copy, Clone, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Opacity {
+    Full,
+    Cutout,
+    Translucent,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BlockConfig {
+    id: u8,
+    name: String,
+    color: [u8; 4],
+    faces: Option<FaceConfig>,
+    #[serde(default = "default_opacity")]
+    opacity: Opacity,
+    #[serde(default = "default_true")]
+    collidable: bool,
+    #[serde(default)]
+    fluid: bool,
+    #[serde(default)]
+    granular: bool,
+    #[serde(default)]
+    light: u8,
+    #[serde(default = "default_light_color")]
+    light_color: [u8; 3],
+    #[serde(default)]
+    minecraft: Vec<String>,
+}
+
+fn default_opacity() -> Opacity {
+    Opacity::Full
+}
+
+fn default_light_color() -> [u8; 3] {
+    [255, 255, 255]
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn to_rgba(color: [u8; 4]) -> [f32; 4] {
+    color.map(|channel| channel as f32 / 255.0)
+}
+
+pub struct BlockDefinition {
+    pub id: u8,
+    pub name: String,
+    pub color: [f32; 4],
+    pub top_color: [f32; 4],
+    pub side_color: [f32; 4],
+    pub bottom_color: [f32; 4],
+    // used instead of the face colors below the water level
+    pub submerged_color: Option<[f32; 4]>,
+    pub opacity: Opacity,
+    pub collidable: bool,
+    pub fluid: bool,
+    // falls when the voxel below is empty, like sand and gravel
+    pub granular: bool,
+    pub light_level: u8,
+    // light given off per red, green and blue channel, the light level scaled by the light color
+    pub emission: [u8; 3],
+    // Minecraft block names for schematics, the first one is used when exporting
+    pub minecraft: Vec<String>,
+}
+
+impl BlockDefinition {
+    pub fn face_color(&self, side: &Side) -> [f32; 4] {
+        match side {
+            Side::Top => self.top_color,
+            Side::Bottom => self.bottom_color,
+            _ => self.side_color,
+        }
+    }
+}
+
+pub struct BlockRegistry {
+    // indexed by block id
+    blocks: Vec<Option<BlockDefinition>>,
+}
+
+impl BlockRegistry {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let file: BlockFile = toml::from_str(source).map_err(|error| error.to_string())?;
+        let mut blocks: Vec<Option<BlockDefinition>> = (0..=u8::MAX).map(|_| None).collect();
+        for config in file.block {
+            if blocks[config.id as usize].is_some() {
+                return Err(format!("block id {} is used twice", config.id));
+            }
+            let color = to_rgba(config.color);
+            let faces = config.faces.as_ref();
+            blocks[config.id as usize] = Some(BlockDefinition {
+                id: config.id,
+                top_color: faces.and_then(|faces| faces.top).map_or(color, to_rgba),
+                side_color: faces.and_then(|faces| faces.side).map_or(color, to_rgba),
+                bottom_color: faces.and_then(|faces| faces.bottom).map_or(color, to_rgba),
+                submerged_color: faces.and_then(|faces| faces.submerged).map(to_rgba),
+                name: config.name,
+                color,
+                opacity: config.opacity,
+                collidable: config.collidable,
+                fluid: config.fluid,
+                granular: config.granular,
+                light_level: config.light.min(15),
+                emission: config
+                    .light_color
+                    .map(|channel| (config.light.min(15) as f32 * channel as f32 / 255.0).round() as u8),
+                minecraft: config.minecraft,
+            });
+        }
+        let registry = Self { blocks };
+        // the generator places these directly, so a registry without them is unusable
+        for (block_type, name) in BlockType::BUILTIN {
+            match registry.get(block_type) {
+                Some(definition) if definition.name == name => {}
+                _ => return Err(format!("missing built-in block {} with id {}", name, block_type.0)),
+            }
+        }
+        Ok(registry)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = std::fs::read_to_string(path).map_err(|error| error.to_string())?;
+        Self::parse(&source)
+    }
+
+    // falls back to the blocks compiled into the binary when the file is missing or broken
+    pub fn init(path: &Path) {
+        let registry = if path.exists() {
+            Self::load(path).unwrap_or_else(|error| {
+                eprintln!("failed to load {}: {}", path.display(), error);
+                Self::parse(DEFAULT_BLOCKS).unwrap()
+            })
+        } else {
+            Self::parse(DEFAULT_BLOCKS).unwrap()
+        };
+        if REGISTRY.set(registry).is_err() {
+            eprintln!("blocks were used before {} was loaded, keeping the built-in ones", path.display());
+        }
+    }
+
+    pub fn global() -> &'static BlockRegistry {
+        REGISTRY.get_or_init(|| Self::parse(DEFAULT_BLOCKS).unwrap())
+    }
+
+    pub fn get(&self, block_type: BlockType) -> Option<&BlockDefinition> {
+        self.blocks[block_type.0 as usize].as_ref()
+    }
+
+    pub fn find(&self, name: &str) -> Option<&BlockDefinition> {
+        self.iter().find(|definition| definition.name == name)
+    }
+
+    pub fn find_minecraft(&self, name: &str) -> Option<&BlockDefinition> {
+        self.iter()
+            .find(|definition| definition.minecraft.iter().any(|minecraft| minecraft == name))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &BlockDefinition> {
+        self.blocks.iter().flatten()
+    }
+}
@@ -0,0 +1,269 @@
+use crate::chunk::CHUNK_SIZE;
+use crate::region::Region;
+use crate::voxel::BlockType;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+// brushes apply at a fixed rate while the mouse is held so strokes do not depend on the frame rate
+const STEP_INTERVAL: instant::Duration = instant::Duration::from_millis(100);
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Brush {
+    Raise,
+    Lower,
+    Smooth,
+    Flatten,
+    Paint,
+}
+
+impl Brush {
+    pub const ALL: [Brush; 5] = [Brush::Raise, Brush::Lower, Brush::Smooth, Brush::Flatten, Brush::Paint];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Brush::Raise => "Raise",
+            Brush::Lower => "Lower",
+            Brush::Smooth => "Smooth",
+            Brush::Flatten => "Flatten",
+            Brush::Paint => "Paint",
+        }
+    }
+}
+
+pub struct BrushSettings {
+    pub brush: Brush,
+    pub radius: f32,
+    // blocks moved per step at the centre of the brush
+    pub strength: f32,
+    // falloff by distance to the hit point instead of distance in the column plane
+    pub spherical: bool,
+    pub paint_block: BlockType,
+}
+
+// one mouse press to release, recorded as a single undo step by the caller
+pub struct Stroke {
+    pub target_height: i32,
+    last_step: Option<instant::Instant>,
+}
+
+impl Stroke {
+    pub fn new(target_height: i32) -> Self {
+        Self {
+            target_height,
+            last_step: None,
+        }
+    }
+
+    pub fn step_due(&mut self) -> bool {
+        let now = instant::Instant::now();
+        if self.last_step.is_some_and(|last_step| now - last_step < STEP_INTERVAL) {
+            return false;
+        }
+        self.last_step = Some(now);
+        true
+    }
+}
+
+// topmost collidable block of the column, water and air do not count as terrain
+fn surface_height(region: &Region, x: i32, z: i32) -> Option<i32> {
+    (0..CHUNK_SIZE as i32)
+        .rev()
+        .find(|y| region.get_block([x, *y, z]).is_some_and(|block_type| block_type.definition().collidable))
+}
+
+// moves the surface block to the new height, new blocks below it copy whatever was under the old surface
+fn set_column_height(region: &mut Region, x: i32, z: i32, height: i32, new_height: i32) -> usize {
+    let new_height = new_height.clamp(0, CHUNK_SIZE as i32 - 1);
+    let Some(top) = region.get_block([x, height, z]) else {
+        return 0;
+    };
+    let under = region
+        .get_block([x, height - 1, z])
+        .filter(|block_type| block_type.definition().collidable)
+        .unwrap_or(top);
+    let mut changed = 0;
+    for y in height.min(new_height)..=height.max(new_height) {
+        let block_type = if y == new_height {
+            top
+        } else if y < new_height {
+            under
+        } else {
+            BlockType::AIR
+        };
+        if region.set_block([x, y, z], block_type) {
+            changed += 1;
+        }
+    }
+    changed
+}
+
+// paint picks the same columns for the same seed and hit position, so a stroke can be replayed
+fn paint_seed(seed: u32, centre: [i32; 3]) -> u64 {
+    (seed as u64)
+        ^ (centre[0] as i64 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (centre[1] as i64 as u64).wrapping_mul(0xD6E8_FEB8_6659_FD93)
+        ^ (centre[2] as i64 as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+}
+
+fn falloff(distance: f32, radius: f32) -> f32 {
+    let weight = (1.0 - distance / radius).clamp(0.0, 1.0);
+    weight * weight * (3.0 - 2.0 * weight)
+}
+
+// one step of the brush around the hit position, returns how many blocks changed
+pub fn apply(region: &mut Region, settings: &BrushSettings, centre: [i32; 3], target_height: i32) -> usize {
+    let reach = settings.radius.ceil() as i32;
+    let mut heights: Vec<((i32, i32), f32, Option<i32>)> = Vec::new();
+    for dx in -reach..=reach {
+        for dz in -reach..=reach {
+            let (x, z) = (centre[0] + dx, centre[2] + dz);
+            let height = surface_height(region, x, z);
+            let dy = if settings.spherical {
+                height.map_or(0, |height| height - centre[1])
+            } else {
+                0
+            };
+            let distance = ((dx * dx + dy * dy + dz * dz) as f32).sqrt();
+            heights.push(((x, z), falloff(distance, settings.radius), height));
+        }
+    }
+    // smoothing reads the heights from before this step so the result does not depend on the visiting order
+    let side = 2 * reach + 1;
+    let height_at = |x: i32, z: i32| {
+        let (dx, dz) = (x - centre[0] + reach, z - centre[2] + reach);
+        if dx < 0 || dz < 0 || dx >= side || dz >= side {
+            return None;
+        }
+        heights[(dx * side + dz) as usize].2
+    };
+    let mut rng = StdRng::seed_from_u64(paint_seed(region.generation_params.seed, centre));
+    let mut changes: Vec<((i32, i32), i32, i32)> = Vec::new();
+    let mut painted: Vec<[i32; 3]> = Vec::new();
+    for ((x, z), weight, height) in heights.iter() {
+        let (Some(height), true) = (*height, *weight > 0.0) else {
+            continue;
+        };
+        let amount = settings.strength * weight;
+        let new_height = match settings.brush {
+            Brush::Raise => height + amount.round() as i32,
+            Brush::Lower => height - amount.round() as i32,
+            Brush::Smooth => {
+                let neighbours: Vec<i32> = (-1..=1)
+                    .flat_map(|nx| (-1..=1).map(move |nz| (x + nx, z + nz)))
+                    .filter_map(|(nx, nz)| height_at(nx, nz))
+                    .collect();
+                let average = neighbours.iter().sum::<i32>() as f32 / neighbours.len() as f32;
+                height + ((average - height as f32) * amount.min(1.0)).round() as i32
+            }
+            Brush::Flatten => height + ((target_height - height) as f32 * amount.min(1.0)).round() as i32,
+            Brush::Paint => {
+                if rng.gen::<f32>() < amount.min(1.0) {
+                    painted.push([*x, height, *z]);
+                }
+                height
+            }
+        };
+        if new_height != height {
+            changes.push(((*x, *z), height, new_height));
+        }
+    }
+    let mut changed = 0;
+    for ((x, z), height, new_height) in changes {
+        changed += set_column_height(region, x, z, height, new_height);
+    }
+    for position in painted {
+        if region.set_block(position, settings.paint_block) {
+            changed += 1;
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLOOR: i32 = 10;
+
+    fn flat_region() -> Region {
+        let mut region = Region::empty([0, 0]);
+        region.fill_box([-8, 0, -8], [8, FLOOR, 8], BlockType::STONE);
+        region
+    }
+
+    fn settings(brush: Brush, strength: f32) -> BrushSettings {
+        BrushSettings {
+            brush,
+            radius: 3.0,
+            strength,
+            spherical: false,
+            paint_block: BlockType::GRANITE,
+        }
+    }
+
+    fn heights(region: &Region) -> Vec<i32> {
+        (-5..=5)
+            .flat_map(|x| (-5..=5).map(move |z| (x, z)))
+            .map(|(x, z)| surface_height(region, x, z).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn raise_and_lower_move_the_centre_most() {
+        let mut region = flat_region();
+        apply(&mut region, &settings(Brush::Raise, 2.0), [0, FLOOR, 0], FLOOR);
+        assert_eq!(surface_height(&region, 0, 0), Some(FLOOR + 2));
+        assert_eq!(region.get_block([0, FLOOR + 1, 0]), Some(BlockType::STONE));
+        assert_eq!(surface_height(&region, 2, 0), Some(FLOOR + 1));
+        assert_eq!(surface_height(&region, 3, 0), Some(FLOOR));
+
+        let mut region = flat_region();
+        apply(&mut region, &settings(Brush::Lower, 2.0), [0, FLOOR, 0], FLOOR);
+        assert_eq!(surface_height(&region, 0, 0), Some(FLOOR - 2));
+        assert_eq!(region.get_block([0, FLOOR, 0]), Some(BlockType::AIR));
+        assert_eq!(surface_height(&region, 3, 3), Some(FLOOR));
+    }
+
+    #[test]
+    fn flatten_pulls_columns_to_the_target() {
+        let mut region = flat_region();
+        region.fill_box([0, FLOOR + 1, 0], [0, FLOOR + 4, 0], BlockType::STONE);
+        region.fill_box([-1, FLOOR - 2, 0], [-1, FLOOR, 0], BlockType::AIR);
+        apply(&mut region, &settings(Brush::Flatten, 1.0), [0, FLOOR + 4, 0], FLOOR);
+        assert_eq!(surface_height(&region, 0, 0), Some(FLOOR));
+        // a weaker pull further out only closes part of the gap
+        let pit = surface_height(&region, -1, 0).unwrap();
+        assert!(pit > FLOOR - 3 && pit < FLOOR);
+    }
+
+    #[test]
+    fn smooth_levels_a_spike() {
+        let mut region = flat_region();
+        region.fill_box([0, FLOOR + 1, 0], [0, FLOOR + 6, 0], BlockType::STONE);
+        apply(&mut region, &settings(Brush::Smooth, 1.0), [0, FLOOR + 6, 0], FLOOR);
+        // the spike drops to the average of its neighbourhood, the flat ground around it stays
+        assert_eq!(surface_height(&region, 0, 0), Some(FLOOR + 1));
+        assert!(heights(&region).iter().all(|height| (FLOOR..=FLOOR + 1).contains(height)));
+    }
+
+    #[test]
+    fn paint_is_seeded_by_the_world_and_the_hit_position() {
+        let painted = |centre: [i32; 3]| {
+            let mut region = flat_region();
+            apply(&mut region, &settings(Brush::Paint, 0.6), centre, FLOOR);
+            let painted: Vec<(i32, i32)> = (-8..=8)
+                .flat_map(|x| (-8..=8).map(move |z| (x, z)))
+                .filter(|(x, z)| region.get_block([*x, FLOOR, *z]) == Some(BlockType::GRANITE))
+                .collect();
+            // only the surface gets painted
+            assert!((-8..=8).all(|x| (-8..=8).all(|z| region.get_block([x, FLOOR - 1, z]) == Some(BlockType::STONE))));
+            painted
+        };
+        let first = painted([0, FLOOR, 0]);
+        assert!(!first.is_empty());
+        assert!(first.iter().all(|(x, z)| x * x + z * z < 9));
+        assert_eq!(first, painted([0, FLOOR, 0]));
+        assert_ne!(first, painted([1, FLOOR, 0]));
+    }
+}
@@ -26,6 +26,29 @@ impl CameraUniform {
     pub fn update_view_proj(&mut self, camera: &mut DollyCamera, time_delta: f32) {
         self.view_proj = camera.build_view_projection_matrix(time_delta).into();
     }
+
+    // unprojects a cursor position in pixels to a world space ray pointing into the scene
+    pub fn cursor_ray(&self, cursor: [f32; 2], screen_size: [f32; 2]) -> ([f32; 3], [f32; 3]) {
+        use cgmath::SquareMatrix;
+        let inverse = cgmath::Matrix4::from(self.view_proj)
+            .invert()
+            .unwrap_or(cgmath::Matrix4::identity());
+        let ndc_x = cursor[0] / screen_size[0] * 2.0 - 1.0;
+        let ndc_y = 1.0 - cursor[1] / screen_size[1] * 2.0;
+        let near = inverse * cgmath::Vector4::new(ndc_x, ndc_y, 0.0, 1.0);
+        let far = inverse * cgmath::Vector4::new(ndc_x, ndc_y, 1.0, 1.0);
+        // depth 1 can land behind the camera with this projection, in that case the second
+        // point is taken halfway to where the unprojected w crosses zero
+        let depth = if near.w * far.w > 0.0 {
+            1.0
+        } else {
+            0.5 * near.w / (near.w - far.w)
+        };
+        let far = inverse * cgmath::Vector4::new(ndc_x, ndc_y, depth, 1.0);
+        let near = near.truncate() / near.w;
+        let far = far.truncate() / far.w;
+        (near.into(), (far - near).into())
+    }
 }
 
 pub struct DollyCamera {
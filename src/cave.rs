@@ -0,0 +1,281 @@
+use crate::chunk::{Chunk, CHUNK_SIZE};
+use crate::continent::ContinentCells;
+use crate::generation_params::GenerationParams;
+use crate::voxel::{BlockType, Voxel};
+
+use noise::core::perlin::perlin_2d;
+use noise::permutationtable::PermutationTable;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::f64::consts::TAU;
+
+const WORM_STEP: f64 = 1.0;
+const WORM_NOISE_SCALE: f64 = 0.05;
+const WORM_YAW_STRENGTH: f64 = 0.35;
+const WORM_PITCH_STRENGTH: f64 = 0.15;
+const WORM_MAX_PITCH: f64 = 1.2;
+const WORM_DEPTH_CORRECTION: f64 = 0.08;
+// worms that are not openings stay this many blocks under the surface
+const CAVE_SURFACE_MARGIN: usize = 4;
+
+pub struct Worm {
+    pub nodes: Vec<[f64; 3]>,
+    pub radii: Vec<f64>,
+    pub is_opening: bool,
+}
+
+impl Worm {
+    fn generate(
+        start: [f64; 3],
+        is_opening: bool,
+        rng: &mut StdRng,
+        params: &GenerationParams,
+        perm_table: &PermutationTable,
+    ) -> Self {
+        let mut nodes = Vec::with_capacity(params.cave_worm_length as usize);
+        let mut radii = Vec::with_capacity(params.cave_worm_length as usize);
+        let noise_offset = rng.gen::<f64>() * 1000.0;
+        let mut yaw = rng.gen_range(0.0..TAU);
+        let mut pitch = if is_opening {
+            -0.8
+        } else {
+            rng.gen_range(-0.3..0.3)
+        };
+        let mut position = start;
+        for step in 0..params.cave_worm_length {
+            let t = step as f64 * WORM_NOISE_SCALE;
+            nodes.push(position);
+            radii.push(
+                params.cave_worm_radius
+                    * (1.0 + 0.3 * perlin_2d([noise_offset, t + 500.0].into(), perm_table)),
+            );
+            yaw += perlin_2d([t, noise_offset].into(), perm_table) * WORM_YAW_STRENGTH;
+            pitch += perlin_2d([noise_offset, t].into(), perm_table) * WORM_PITCH_STRENGTH;
+            if position[1] < params.cave_min_y as f64 {
+                pitch += WORM_DEPTH_CORRECTION;
+            } else if position[1] > params.cave_max_y as f64 {
+                pitch -= WORM_DEPTH_CORRECTION;
+            } else {
+                pitch *= 0.9;
+            }
+            pitch = pitch.clamp(-WORM_MAX_PITCH, WORM_MAX_PITCH);
+            position[0] += yaw.cos() * pitch.cos() * WORM_STEP;
+            position[1] += pitch.sin() * WORM_STEP;
+            position[2] += yaw.sin() * pitch.cos() * WORM_STEP;
+        }
+        Self {
+            nodes,
+            radii,
+            is_opening,
+        }
+    }
+}
+
+fn chunk_seed(seed: u32, chunk_x: i32, chunk_z: i32) -> u64 {
+    (seed as u64)
+        ^ (chunk_x as i64 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (chunk_z as i64 as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+}
+
+// every chunk spawns the same worms no matter which chunk asks for them,
+// which is what lets a tunnel continue seamlessly into the neighbours
+pub fn worms_for_chunk(
+    chunk_x: i32,
+    chunk_z: i32,
+    params: &GenerationParams,
+    perm_table: &PermutationTable,
+    continent_cells: &ContinentCells,
+) -> Vec<Worm> {
+    let mut rng = StdRng::seed_from_u64(chunk_seed(params.seed, chunk_x, chunk_z));
+    let mut worms = Vec::new();
+    for i in 0..params.cave_worm_count + params.cave_openings {
+        let is_opening = i >= params.cave_worm_count;
+        let x = rng.gen_range(0..CHUNK_SIZE) as f64 + (chunk_x * CHUNK_SIZE as i32) as f64;
+        let z = rng.gen_range(0..CHUNK_SIZE) as f64 + (chunk_z * CHUNK_SIZE as i32) as f64;
+        let y = if is_opening {
+            Chunk::surface_height(
+                x / CHUNK_SIZE as f64,
+                z / CHUNK_SIZE as f64,
+                params,
+                perm_table,
+                continent_cells,
+            )
+        } else {
+            let max_y = params.cave_max_y.max(params.cave_min_y + 1);
+            rng.gen_range(params.cave_min_y..max_y) as f64
+        };
+        worms.push(Worm::generate([x, y, z], is_opening, &mut rng, params, perm_table));
+    }
+    worms
+}
+
+pub fn carve_worms(
+    blocks_vector: &mut [Voxel],
+    world_position: [f32; 3],
+    column_heights: &[usize],
+    params: &GenerationParams,
+    perm_table: &PermutationTable,
+    continent_cells: &ContinentCells,
+) {
+    let chunk_x = world_position[0] as i32;
+    let chunk_z = world_position[2] as i32;
+    let origin = [chunk_x * CHUNK_SIZE as i32, chunk_z * CHUNK_SIZE as i32];
+    let reach = params.cave_worm_length as f64 * WORM_STEP + params.cave_worm_radius * 1.3;
+    let chunk_range = (reach / CHUNK_SIZE as f64).ceil() as i32;
+    for source_x in chunk_x - chunk_range..=chunk_x + chunk_range {
+        for source_z in chunk_z - chunk_range..=chunk_z + chunk_range {
+            for worm in worms_for_chunk(source_x, source_z, params, perm_table, continent_cells) {
+                for (node, radius) in worm.nodes.iter().zip(worm.radii.iter()) {
+                    carve_sphere(blocks_vector, origin, column_heights, *node, *radius, worm.is_opening);
+                }
+            }
+        }
+    }
+}
+
+fn carve_sphere(
+    blocks_vector: &mut [Voxel],
+    origin: [i32; 2],
+    column_heights: &[usize],
+    centre: [f64; 3],
+    radius: f64,
+    is_opening: bool,
+) {
+    let local = [centre[0] - origin[0] as f64, centre[1], centre[2] - origin[1] as f64];
+    let min = local.map(|c| (c - radius).floor().max(0.0) as usize);
+    let max = local.map(|c| (c + radius).ceil().min(CHUNK_SIZE as f64 - 1.0));
+    if max.iter().any(|c| *c < 0.0) {
+        return;
+    }
+    let max = max.map(|c| c as usize);
+    for x in min[0]..=max[0] {
+        for z in min[2]..=max[2] {
+            let column_height = column_heights[x * CHUNK_SIZE + z];
+            for y in min[1].max(1)..=max[1] {
+                if !is_opening && y + CAVE_SURFACE_MARGIN > column_height {
+                    continue;
+                }
+                let dx = x as f64 - local[0];
+                let dy = y as f64 - local[1];
+                let dz = z as f64 - local[2];
+                if dx * dx + dy * dy + dz * dz > radius * radius {
+                    continue;
+                }
+                let index = Chunk::linearize(x, y, z);
+                let block_type = blocks_vector[index].block_type;
+                if !block_type.definition().fluid && block_type != BlockType::BEDROCK {
+                    blocks_vector[index] = Voxel::new(BlockType::AIR);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BEDROCK_HEIGHT: usize = 3;
+    const SURFACE: usize = 40;
+
+    fn params() -> GenerationParams {
+        GenerationParams {
+            seed: 7,
+            cave_worm_count: 6,
+            cave_min_y: 1,
+            cave_max_y: 12,
+            cave_openings: 0,
+            ..GenerationParams::default()
+        }
+    }
+
+    // solid stone up to the surface on a bedrock floor, carved the way a chunk does it
+    fn carved_chunk(chunk_x: f32, params: &GenerationParams) -> Vec<Voxel> {
+        let mut blocks = vec![Voxel::new(BlockType::AIR); CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE];
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                for y in 0..SURFACE {
+                    let block_type = if y < BEDROCK_HEIGHT { BlockType::BEDROCK } else { BlockType::STONE };
+                    blocks[Chunk::linearize(x, y, z)] = Voxel::new(block_type);
+                }
+            }
+        }
+        let perm_table = PermutationTable::new(params.seed);
+        let continent_cells = ContinentCells::new(params, [0, 0]);
+        carve_worms(
+            &mut blocks,
+            [chunk_x, 0.0, 0.0],
+            &[SURFACE; CHUNK_SIZE * CHUNK_SIZE],
+            params,
+            &perm_table,
+            &continent_cells,
+        );
+        blocks
+    }
+
+    #[test]
+    fn carving_is_deterministic() {
+        let params = params();
+        let first = carved_chunk(0.0, &params);
+        let second = carved_chunk(0.0, &params);
+        assert!(first.iter().zip(second.iter()).all(|(a, b)| a.block_type == b.block_type));
+        let other_seed = carved_chunk(0.0, &GenerationParams { seed: 8, ..params });
+        assert!(first.iter().zip(other_seed.iter()).any(|(a, b)| a.block_type != b.block_type));
+    }
+
+    #[test]
+    fn carving_stays_between_the_bedrock_and_the_surface() {
+        let params = params();
+        for chunk_x in [-1.0, 0.0, 1.0] {
+            let blocks = carved_chunk(chunk_x, &params);
+            let mut carved = 0;
+            for x in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    for y in 0..CHUNK_SIZE {
+                        let block_type = blocks[Chunk::linearize(x, y, z)].block_type;
+                        if y < BEDROCK_HEIGHT {
+                            assert_eq!(block_type, BlockType::BEDROCK);
+                        } else if y < SURFACE && block_type == BlockType::AIR {
+                            assert!(y + CAVE_SURFACE_MARGIN <= SURFACE);
+                            carved += 1;
+                        }
+                    }
+                }
+            }
+            assert!(carved > 0);
+        }
+    }
+
+    #[test]
+    fn worms_from_neighbouring_chunks_are_clipped_to_the_chunk() {
+        let params = params();
+        let perm_table = PermutationTable::new(params.seed);
+        let continent_cells = ContinentCells::new(&params, [0, 0]);
+        let blocks = carved_chunk(0.0, &params);
+        // every carved voxel lies inside a sphere of one of the worms that can reach this chunk
+        let worms: Vec<Worm> = (-2..=2)
+            .flat_map(|source_x| (-2..=2).map(move |source_z| (source_x, source_z)))
+            .flat_map(|(source_x, source_z)| worms_for_chunk(source_x, source_z, &params, &perm_table, &continent_cells))
+            .collect();
+        let mut carved_from_outside = false;
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                for y in BEDROCK_HEIGHT..SURFACE {
+                    if blocks[Chunk::linearize(x, y, z)].block_type != BlockType::AIR {
+                        continue;
+                    }
+                    let position = [x as f64, y as f64, z as f64];
+                    let carving_worm = worms.iter().find(|worm| {
+                        worm.nodes.iter().zip(worm.radii.iter()).any(|(node, radius)| {
+                            (0..3).map(|axis| (position[axis] - node[axis]).powi(2)).sum::<f64>() <= radius * radius
+                        })
+                    });
+                    let start = carving_worm.expect("carved outside every worm").nodes[0];
+                    carved_from_outside |= !(0.0..CHUNK_SIZE as f64).contains(&start[0])
+                        || !(0.0..CHUNK_SIZE as f64).contains(&start[2]);
+                }
+            }
+        }
+        assert!(carved_from_outside);
+    }
+}
@@ -1,9 +1,20 @@
+use crate::block_registry::Opacity;
+use crate::cave;
+use crate::storage::{ChunkStorage, StorageKind};
+use crate::continent::{self, ContinentCells};
+use crate::falling;
+use crate::fluid::FULL_FLUID_LEVEL;
+use crate::light::{self, MAX_LIGHT};
+use crate::light_storage::LightStorage;
+use crate::generation_params::GenerationParams;
+use crate::structure::{self, StructureTemplate};
 use crate::vertex::Vertex;
 use crate::voxel::{BlockType, Voxel};
 use crate::{quad::Quad, quad::Side};
 
 use enum_iterator::all;
-use noise::core::perlin::perlin_2d;
+use noise::core::perlin::{perlin_2d, perlin_3d};
+use std::collections::HashMap;
 
 pub const CHUNK_SIZE: usize = 64;
 const CHUNK_SQUARED: usize = CHUNK_SIZE * CHUNK_SIZE;
@@ -11,8 +22,18 @@ const CHUNK_CUBED: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
 
 pub struct Chunk {
     pub world_position: [f32; 3],
-    pub blocks_vector: Vec<Voxel>,
+    pub blocks: ChunkStorage,
     pub water_level: usize,
+    // levels of flowing fluid by linear index, fluid voxels without an entry are sources
+    pub fluid_levels: HashMap<usize, u8>,
+    // skylight of every voxel, filled in by the region once all its chunks exist
+    pub sky_light: LightStorage<u8>,
+    // red, green and blue light from emissive blocks like lava and torches, filled in alongside skylight
+    pub block_light: LightStorage<[u8; 3]>,
+    // set once the chunk differs from what the generator produced
+    pub modified: bool,
+    // set when the cached mesh no longer matches the voxels
+    pub dirty: bool,
 }
 impl Chunk {
 
@@ -45,111 +66,348 @@ impl Chunk {
         let z = index - x * CHUNK_SQUARED - y * CHUNK_SIZE;
         return [x,y,z];
     }
+    pub fn get_voxel(&self, x: usize, y: usize, z: usize) -> Voxel {
+        Voxel::new(self.blocks.get(x, y, z))
+    }
+
+    // a new block starts out as a source, flowing fluid sets its level afterwards
+    pub fn set_voxel(&mut self, x: usize, y: usize, z: usize, voxel: Voxel) {
+        self.blocks.set(x, y, z, voxel.block_type);
+        self.fluid_levels.remove(&Self::linearize(x, y, z));
+        self.modified = true;
+        self.dirty = true;
+    }
+
+    pub fn fluid_level(&self, x: usize, y: usize, z: usize) -> u8 {
+        self.fluid_levels
+            .get(&Self::linearize(x, y, z))
+            .copied()
+            .unwrap_or(FULL_FLUID_LEVEL)
+    }
+
+    pub fn set_fluid_level(&mut self, x: usize, y: usize, z: usize, level: u8) {
+        let index = Self::linearize(x, y, z);
+        if level >= FULL_FLUID_LEVEL {
+            self.fluid_levels.remove(&index);
+        } else {
+            self.fluid_levels.insert(index, level);
+        }
+        self.dirty = true;
+    }
+
+    pub fn convert_storage(&mut self, kind: StorageKind) {
+        if self.blocks.kind() != kind {
+            self.blocks = ChunkStorage::from_dense(&self.blocks.to_dense(), kind);
+        }
+    }
+
+    pub fn surface_height(
+        nx: f64,
+        nz: f64,
+        generation_params: &GenerationParams,
+        perm_table: &noise::permutationtable::PermutationTable,
+        continent_cells: &ContinentCells,
+    ) -> f64 {
+        let height = Self::perlin2d_octaves(
+            nx,
+            nz,
+            generation_params.octaves as i32,
+            generation_params.frequency,
+            perm_table,
+        ) * generation_params.noise_multiplier
+            + generation_params.ground_level as f64;
+        continent::apply_continent_mask(height, nx, nz, generation_params, continent_cells)
+    }
+
+    fn rock_at(
+        nx: f64,
+        y: usize,
+        nz: f64,
+        generation_params: &GenerationParams,
+        perm_table: &noise::permutationtable::PermutationTable,
+    ) -> BlockType {
+        const STRATA: [BlockType; 4] = [
+            BlockType::STONE,
+            BlockType::GRANITE,
+            BlockType::SANDSTONE,
+            BlockType::SLATE,
+        ];
+        // warping the layer boundaries keeps the banding from looking ruler straight
+        let warp = perlin_2d([nx * 3.0 + 100.0, nz * 3.0 + 100.0].into(), perm_table)
+            * generation_params.strata_warp;
+        let layer = ((y as f64 + warp) / generation_params.strata_thickness.max(1) as f64).floor() as i32;
+        STRATA[layer.rem_euclid(STRATA.len() as i32) as usize]
+    }
+
     pub fn new_perlin2d(
         world_position: [f32; 3],
-        frequency: f64,
-        octaves: usize,
         perm_table: &noise::permutationtable::PermutationTable,
-        ground_level: f64,
-        noise_multiplier: f64,
-        water_level: usize,
-        dirt_layer_height: i32,
+        generation_params: &GenerationParams,
+        continent_cells: &ContinentCells,
+        structure_templates: &[StructureTemplate],
     ) -> Self {
-        let mut blocks_vector:Vec<Voxel> = vec![Voxel::new(false, BlockType::None); CHUNK_CUBED];
+        let water_level = generation_params.water_level as usize;
+        let dirt_layer_height = generation_params.dirt_layer_height as i32;
+        // looked up by name, registries from before sand and gravel existed get grass shores instead
+        let sand = BlockType::from_name("sand");
+        let gravel = BlockType::from_name("gravel");
+        let mut blocks_vector:Vec<Voxel> = vec![Voxel::new(BlockType::AIR); CHUNK_CUBED];
+        let mut column_heights: Vec<usize> = vec![0; CHUNK_SQUARED];
         for x in 0..CHUNK_SIZE {
             for z in 0..CHUNK_SIZE {
                 let nx = (x as f64 / CHUNK_SIZE as f64) + world_position[0] as f64;
                 let nz = (z as f64 / CHUNK_SIZE as f64) + world_position[2] as f64;
-                let y_level = Self::perlin2d_octaves(nx, nz, octaves as i32, frequency, perm_table)
-                    * noise_multiplier
-                    + ground_level;
+                let y_level =
+                    Self::surface_height(nx, nz, generation_params, perm_table, continent_cells);
+                column_heights[x * CHUNK_SIZE + z] = y_level as usize;
+                let bedrock_height = 1 + ((perlin_2d([nx * 16.0, nz * 16.0].into(), perm_table) + 1.0)
+                    / 2.0
+                    * (generation_params.bedrock_thickness.max(1) - 1) as f64)
+                    .round() as usize;
+                // shores and shallow sea floors are sand, deeper sea floors gravel
+                let shore = if y_level < water_level as f64 - 3.0 {
+                    gravel
+                } else if y_level <= water_level as f64 + 1.0 {
+                    sand
+                } else {
+                    None
+                };
                 for y in 0..=y_level as usize {
                     if y == y_level as usize {
-                        blocks_vector[Self::linearize(x, y, z)] = Voxel::new(true, BlockType::Grass);
+                        blocks_vector[Self::linearize(x, y, z)] = Voxel::new(shore.unwrap_or(BlockType::GRASS));
                     } else if y > (y_level - dirt_layer_height as f64) as usize {
-                        blocks_vector[Self::linearize(x, y, z)] = Voxel::new(true, BlockType::Dirt);
+                        blocks_vector[Self::linearize(x, y, z)] = Voxel::new(shore.unwrap_or(BlockType::DIRT));
+                    } else if y < bedrock_height {
+                        blocks_vector[Self::linearize(x, y, z)] = Voxel::new(BlockType::BEDROCK);
                     } else {
-                        blocks_vector[Self::linearize(x, y, z)] = Voxel::new(true, BlockType::Stone);
+                        let rock = Self::rock_at(nx, y, nz, generation_params, perm_table);
+                        blocks_vector[Self::linearize(x, y, z)] = Voxel::new(rock);
                     }
                 }
-                if blocks_vector[Self::linearize(x,water_level,z)].block_type == BlockType::None {
-                    blocks_vector[Self::linearize(x,water_level,z)] = Voxel::new(true, BlockType::Water);
+            }
+        }
+        cave::carve_worms(
+            &mut blocks_vector,
+            world_position,
+            &column_heights,
+            generation_params,
+            perm_table,
+            continent_cells,
+        );
+        // caves can leave sand hanging over them
+        falling::settle_generated(&mut blocks_vector);
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let definition = blocks_vector[Self::linearize(x,water_level,z)].block_type.definition();
+                if !definition.collidable && !definition.fluid
+                    && column_heights[x * CHUNK_SIZE + z] < water_level
+                {
+                    blocks_vector[Self::linearize(x,water_level,z)] = Voxel::new(BlockType::WATER);
                 }
             }
         }
+        structure::place_structures(
+            &mut blocks_vector,
+            world_position,
+            &column_heights,
+            structure_templates,
+            generation_params,
+            perm_table,
+            continent_cells,
+        );
         return Self {
             world_position,
             water_level,
-            blocks_vector
+            blocks: ChunkStorage::from_dense(&blocks_vector, StorageKind::Palette),
+            fluid_levels: HashMap::new(),
+            sky_light: LightStorage::new_uniform(0),
+            block_light: LightStorage::new_uniform([0; 3]),
+            modified: false,
+            dirty: true,
         };
     }
 
-    pub fn handle_directional_move(&self, position: [usize; 3], direction: i32, axis: usize) -> bool {
-        if position[axis] == 0 && direction < 0 {
-            return true;
+    fn island_density(
+        nx: f64,
+        y: usize,
+        nz: f64,
+        generation_params: &GenerationParams,
+        perm_table: &noise::permutationtable::PermutationTable,
+    ) -> f64 {
+        let frequency = generation_params.island_frequency;
+        let ny = y as f64 / CHUNK_SIZE as f64;
+        let mut density = 0.0;
+        for i in 1..=generation_params.octaves {
+            density += 1.0 / i as f64
+                * perlin_3d(
+                    [
+                        i as f64 * nx * frequency,
+                        i as f64 * ny * frequency,
+                        i as f64 * nz * frequency,
+                    ]
+                    .into(),
+                    perm_table,
+                );
         }
-        if position[axis] == CHUNK_SIZE - 1 && direction > 0 {
-            return true;
+        // islands only survive inside a band around island_height
+        let band_distance = (y as f64 - generation_params.island_height as f64)
+            / generation_params.island_thickness as f64;
+        density + generation_params.island_density - band_distance * band_distance
+    }
+
+    pub fn new_floating_islands(
+        world_position: [f32; 3],
+        perm_table: &noise::permutationtable::PermutationTable,
+        generation_params: &GenerationParams,
+    ) -> Self {
+        let dirt_layer_height = generation_params.dirt_layer_height as usize;
+        let mut blocks_vector:Vec<Voxel> = vec![Voxel::new(BlockType::AIR); CHUNK_CUBED];
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let nx = (x as f64 / CHUNK_SIZE as f64) + world_position[0] as f64;
+                let nz = (z as f64 / CHUNK_SIZE as f64) + world_position[2] as f64;
+                // walk each column top down so the depth below open sky picks the block
+                let mut depth = 0;
+                for y in (0..CHUNK_SIZE).rev() {
+                    if Self::island_density(nx, y, nz, generation_params, perm_table) <= 0.0 {
+                        depth = 0;
+                        continue;
+                    }
+                    let block_type = if depth == 0 {
+                        BlockType::GRASS
+                    } else if depth <= dirt_layer_height {
+                        BlockType::DIRT
+                    } else {
+                        BlockType::STONE
+                    };
+                    blocks_vector[Self::linearize(x, y, z)] = Voxel::new(block_type);
+                    depth += 1;
+                }
+            }
         }
-        let mut new_position:Vec<i32> = position.clone().iter().map(|x| *x as i32).collect();
+        return Self {
+            world_position,
+            water_level: 0,
+            blocks: ChunkStorage::from_dense(&blocks_vector, StorageKind::Palette),
+            fluid_levels: HashMap::new(),
+            sky_light: LightStorage::new_uniform(0),
+            block_light: LightStorage::new_uniform([0; 3]),
+            modified: false,
+            dirty: true,
+        };
+    }
+
+    // outside_block answers for neighbours across the chunk border, faces on the edge of the loaded world are kept
+    pub fn handle_directional_move(
+        &self,
+        position: [usize; 3],
+        direction: i32,
+        axis: usize,
+        outside_block: &dyn Fn([i32; 3]) -> Option<BlockType>,
+    ) -> bool {
+        let block_type = self.get_voxel(position[0], position[1], position[2]).block_type;
+        let opacity = block_type.definition().opacity;
+        let mut new_position: [i32; 3] = position.map(|c| c as i32);
         new_position[axis] += direction;
-        let block_at_new_position = self.blocks_vector[Self::linearize(new_position[0] as usize, new_position[1] as usize, new_position[2] as usize)];
-        if block_at_new_position.is_active && block_at_new_position.block_type != BlockType::Water
-        {
+        let neighbour = if new_position.iter().all(|c| (0..CHUNK_SIZE as i32).contains(c)) {
+            self.get_voxel(new_position[0] as usize, new_position[1] as usize, new_position[2] as usize).block_type
+        } else {
+            let mut world_position = new_position;
+            world_position[0] += self.world_position[0] as i32 * CHUNK_SIZE as i32;
+            world_position[2] += self.world_position[2] as i32 * CHUNK_SIZE as i32;
+            match outside_block(world_position) {
+                Some(neighbour) => neighbour,
+                None => return true,
+            }
+        };
+        if neighbour.definition().opacity == Opacity::Full {
+            return false;
+        }
+        if neighbour == block_type && opacity == Opacity::Translucent {
             return false;
         }
         return true;
     }
 
-    pub fn build_mesh(&self, index_start: u32, water_index_start:u32) -> (Vec<Vertex>, Vec<u32>, Vec<Vertex>, Vec<u32>) {
+    // faces are shaded by the light of the voxel they look into, outside_light answers with the sky and
+    // block light of voxels beyond this chunk and anything it does not know is treated as open sky
+    pub fn build_mesh(
+        &self,
+        index_start: u32,
+        translucent_index_start: u32,
+        outside_block: &dyn Fn([i32; 3]) -> Option<BlockType>,
+        outside_light: &dyn Fn([i32; 3]) -> Option<(u8, [u8; 3])>,
+    ) -> (Vec<Vertex>, Vec<u32>, Vec<Vertex>, Vec<u32>) {
         let mut vertices: Vec<Vertex> = Vec::new();
         let mut indices: Vec<u32> = Vec::new();
         let mut vertex_index: u32 = index_start.clone();
-        let mut water_vertices: Vec<Vertex> = Vec::new();
-        let mut water_indices: Vec<u32> = Vec::new();
-        let mut water_vertex_index: u32 = water_index_start.clone();
-        for x in 0..CHUNK_SIZE {    
-            for y in 0..CHUNK_SIZE {
-                for z in 0..CHUNK_SIZE {
-                    let block:Voxel = self.blocks_vector[Self::linearize(x,y,z)];
-                    if block.is_active {
-                        let world_pos = [
-                            (self.world_position[0] * CHUNK_SIZE as f32) + x as f32,
-                            (self.world_position[1] * CHUNK_SIZE as f32) + y as f32,
-                            (self.world_position[2] * CHUNK_SIZE as f32) + z as f32,
-                        ];
-                        for side in all::<Side>() {
-                            let quad = Quad::new(&side, world_pos[0], world_pos[1], world_pos[2]);
-                            let (axis, direction) = Quad::get_axis_and_direction_for_side(&side);
-                            if self.handle_directional_move([x,y,z], direction, axis) {
-                                let mut color = Voxel::get_rgb_for_type(block.block_type);
-                                if block.block_type == BlockType::Water {
-                                    if side == Side::Top {
-                                        water_vertices.append(&mut quad.get_corner_vertices(color));
-                                        water_indices.append(&mut quad.get_indices(water_vertex_index));
-                                        water_vertex_index += 4;
-                                    }
-                                    continue;
-                                }
-                                if block.block_type == BlockType::Grass
-                                    && (side != Side::Top || y < self.water_level)
-                                {
-                                    color = Voxel::get_rgb_for_type(BlockType::Dirt);
-                                }
-                                let multi = Quad::get_color_multiplier_for_side(&side);
-                                for i in 0..3 {
-                                    color[i] *= multi;
-                                }
-                                vertices.append(&mut quad.get_corner_vertices(color));
-                                indices.append(&mut quad.get_indices(vertex_index));
-                                vertex_index += 4;
-                            }
+        let mut translucent_vertices: Vec<Vertex> = Vec::new();
+        let mut translucent_indices: Vec<u32> = Vec::new();
+        let mut translucent_vertex_index: u32 = translucent_index_start;
+        self.blocks.for_each_active(&mut |x, y, z, block_type| {
+            let world_pos = [
+                (self.world_position[0] * CHUNK_SIZE as f32) + x as f32,
+                (self.world_position[1] * CHUNK_SIZE as f32) + y as f32,
+                (self.world_position[2] * CHUNK_SIZE as f32) + z as f32,
+            ];
+            // flowing fluid is drawn lower the further it got from its source, unless more fluid sits on top
+            let fluid_height = if block_type.definition().fluid
+                && (y == CHUNK_SIZE - 1 || self.get_voxel(x, y + 1, z).block_type != block_type)
+            {
+                self.fluid_level(x, y, z) as f32 / FULL_FLUID_LEVEL as f32
+            } else {
+                1.0
+            };
+            for side in all::<Side>() {
+                let mut quad = Quad::new(&side, world_pos[0], world_pos[1], world_pos[2]);
+                for corner in quad.corners.iter_mut() {
+                    if corner[1] > world_pos[1] {
+                        corner[1] = world_pos[1] - 0.5 + fluid_height;
+                    }
+                }
+                let (axis, direction) = Quad::get_axis_and_direction_for_side(&side);
+                if self.handle_directional_move([x,y,z], direction, axis, outside_block) {
+                    let definition = block_type.definition();
+                    let mut color = definition.face_color(&side);
+                    if y < self.water_level {
+                        color = definition.submerged_color.unwrap_or(color);
+                    }
+                    let mut facing = [x as i32, y as i32, z as i32];
+                    facing[axis] += direction;
+                    let (sky_light, block_light) = if facing.iter().all(|c| (0..CHUNK_SIZE as i32).contains(c)) {
+                        let [fx, fy, fz] = facing.map(|c| c as usize);
+                        (self.sky_light.get(fx, fy, fz), self.block_light.get(fx, fy, fz))
+                    } else {
+                        let mut world_facing = world_pos.map(|c| c as i32);
+                        world_facing[axis] += direction;
+                        outside_light(world_facing).unwrap_or((MAX_LIGHT, [0; 3]))
+                    };
+                    // every color channel takes whichever is brighter, the sky or the block light of that color
+                    if definition.light_level == 0 {
+                        for (channel, block_level) in color.iter_mut().zip(block_light) {
+                            *channel *= light::brightness(sky_light.max(block_level));
                         }
                     }
+                    if definition.opacity == Opacity::Translucent {
+                        translucent_vertices.append(&mut quad.get_corner_vertices(color));
+                        translucent_indices.append(&mut quad.get_indices(translucent_vertex_index));
+                        translucent_vertex_index += 4;
+                        continue;
+                    }
+                    // emissive blocks glow evenly instead of taking the per-side shading
+                    if definition.light_level == 0 {
+                        let multi = Quad::get_color_multiplier_for_side(&side);
+                        for i in 0..3 {
+                            color[i] *= multi;
+                        }
+                    }
+                    vertices.append(&mut quad.get_corner_vertices(color));
+                    indices.append(&mut quad.get_indices(vertex_index));
+                    vertex_index += 4;
                 }
             }
-        }
-        return (vertices, indices, water_vertices, water_indices);
+        });
+        return (vertices, indices, translucent_vertices, translucent_indices);
     }
 
 }
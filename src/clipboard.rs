@@ -0,0 +1,235 @@
+use crate::chunk::CHUNK_SIZE;
+use crate::region::{Region, CHUNK_PER_ROW};
+use crate::voxel::BlockType;
+use crate::world_save::{compress, decompress, invalid_data, ByteReader, ByteWriter};
+
+use std::io;
+
+const CLIPBOARD_MAGIC: &[u8; 4] = b"VXCB";
+const CLIPBOARD_VERSION: u16 = 1;
+// a copy never spans more than the loaded region: the size, a full palette of names and one index per block
+const MAX_PAYLOAD_LEN: usize =
+    12 + 2 + 256 * 256 + (CHUNK_PER_ROW as usize * CHUNK_SIZE).pow(2) * CHUNK_SIZE;
+
+// names are length prefixed with a single byte, a longer one would corrupt everything after it
+fn write_name(writer: &mut ByteWriter, name: &str) -> io::Result<()> {
+    let len = u8::try_from(name.len()).map_err(|_| invalid_data(&format!("block name {} is too long", name)))?;
+    writer.u8(len);
+    writer.bytes.extend_from_slice(name.as_bytes());
+    Ok(())
+}
+
+// two corners picked in the world, either may still be missing
+#[derive(Default, Copy, Clone)]
+pub struct Selection {
+    pub first: Option<[i32; 3]>,
+    pub second: Option<[i32; 3]>,
+}
+
+impl Selection {
+    // inclusive min and max corners once both are picked
+    pub fn corners(&self) -> Option<([i32; 3], [i32; 3])> {
+        let (first, second) = (self.first?, self.second?);
+        Some((
+            std::array::from_fn(|axis| first[axis].min(second[axis])),
+            std::array::from_fn(|axis| first[axis].max(second[axis])),
+        ))
+    }
+}
+
+// a copied box of blocks, indexed like chunks: (x * height + y) * depth + z
+pub struct Clipboard {
+    pub size: [i32; 3],
+    pub blocks: Vec<BlockType>,
+}
+
+impl Clipboard {
+    fn index(&self, x: i32, y: i32, z: i32) -> usize {
+        ((x * self.size[1] + y) * self.size[2] + z) as usize
+    }
+
+    // everything outside the loaded chunks is copied as air
+    pub fn copy(region: &Region, min: [i32; 3], max: [i32; 3]) -> Self {
+        let size = std::array::from_fn(|axis| max[axis] - min[axis] + 1);
+        let mut blocks = Vec::with_capacity((size[0] * size[1] * size[2]) as usize);
+        for x in min[0]..=max[0] {
+            for y in min[1]..=max[1] {
+                for z in min[2]..=max[2] {
+                    blocks.push(region.get_block([x, y, z]).unwrap_or(BlockType::AIR));
+                }
+            }
+        }
+        Self { size, blocks }
+    }
+
+    fn remap(&self, size: [i32; 3], source: impl Fn(i32, i32, i32) -> [i32; 3]) -> Self {
+        let mut blocks = Vec::with_capacity(self.blocks.len());
+        for x in 0..size[0] {
+            for y in 0..size[1] {
+                for z in 0..size[2] {
+                    let [sx, sy, sz] = source(x, y, z);
+                    blocks.push(self.blocks[self.index(sx, sy, sz)]);
+                }
+            }
+        }
+        Self { size, blocks }
+    }
+
+    // a quarter turn around the vertical axis, clockwise seen from above
+    pub fn rotate(&self) -> Self {
+        let [width, height, depth] = self.size;
+        self.remap([depth, height, width], |x, y, z| [z, y, depth - 1 - x])
+    }
+
+    pub fn mirror(&self, axis: usize) -> Self {
+        let size = self.size;
+        self.remap(size, |x, y, z| {
+            let mut source = [x, y, z];
+            source[axis] = size[axis] - 1 - source[axis];
+            source
+        })
+    }
+
+    // origin is where the min corner lands, the whole paste is a single undo step
+    pub fn paste(&self, region: &mut Region, origin: [i32; 3], skip_air: bool) -> usize {
+        let mut changed = 0;
+        region.history.begin_group();
+        for x in 0..self.size[0] {
+            for y in 0..self.size[1] {
+                for z in 0..self.size[2] {
+                    let block_type = self.blocks[self.index(x, y, z)];
+                    if skip_air && block_type == BlockType::AIR {
+                        continue;
+                    }
+                    if region.set_block([origin[0] + x, origin[1] + y, origin[2] + z], block_type) {
+                        changed += 1;
+                    }
+                }
+            }
+        }
+        region.history.end_group();
+        changed
+    }
+
+    // blocks are stored by name so files survive id changes in blocks.toml
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut palette: Vec<BlockType> = Vec::new();
+        let mut indices: Vec<u8> = Vec::with_capacity(self.blocks.len());
+        for block_type in self.blocks.iter() {
+            let index = match palette.iter().position(|entry| entry == block_type) {
+                Some(index) => index,
+                None => {
+                    palette.push(*block_type);
+                    palette.len() - 1
+                }
+            };
+            indices.push(index as u8);
+        }
+        let mut payload = ByteWriter::default();
+        for extent in self.size {
+            payload.i32(extent);
+        }
+        payload.u16(palette.len() as u16);
+        for block_type in palette.iter() {
+            write_name(&mut payload, &block_type.definition().name)?;
+        }
+        payload.bytes.extend_from_slice(&indices);
+        let mut writer = ByteWriter::default();
+        writer.bytes.extend_from_slice(CLIPBOARD_MAGIC);
+        writer.u16(CLIPBOARD_VERSION);
+        writer.bytes.extend_from_slice(&compress(&payload.bytes)?);
+        Ok(writer.bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let mut reader = ByteReader::new(bytes);
+        if reader.take(4)? != CLIPBOARD_MAGIC {
+            return Err(invalid_data("not a clipboard file"));
+        }
+        if reader.u16()? > CLIPBOARD_VERSION {
+            return Err(invalid_data("clipboard was saved by a newer, incompatible version"));
+        }
+        let payload = decompress(reader.take(bytes.len() - 6)?, MAX_PAYLOAD_LEN)?;
+        let mut reader = ByteReader::new(&payload);
+        let size = [reader.i32()?, reader.i32()?, reader.i32()?];
+        if size.iter().any(|extent| *extent <= 0) {
+            return Err(invalid_data("empty clipboard"));
+        }
+        let palette_len = reader.u16()? as usize;
+        let mut palette = Vec::with_capacity(palette_len);
+        for _ in 0..palette_len {
+            let len = reader.u8()? as usize;
+            let name = String::from_utf8_lossy(reader.take(len)?).into_owned();
+            let block_type = BlockType::from_name(&name)
+                .ok_or_else(|| invalid_data(&format!("unknown block {}", name)))?;
+            palette.push(block_type);
+        }
+        let count = size
+            .iter()
+            .try_fold(1usize, |count, extent| count.checked_mul(*extent as usize))
+            .ok_or_else(|| invalid_data("clipboard too large"))?;
+        let blocks = reader
+            .take(count)?
+            .iter()
+            .map(|index| palette.get(*index as usize).copied())
+            .collect::<Option<Vec<BlockType>>>()
+            .ok_or_else(|| invalid_data("block index outside of the palette"))?;
+        Ok(Self { size, blocks })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every axis a different length so a swapped axis shows up
+    fn sample() -> Clipboard {
+        let size = [2, 3, 4];
+        let kinds = [BlockType::AIR, BlockType::STONE, BlockType::DIRT, BlockType::GRASS, BlockType::GRANITE];
+        let blocks = (0..24).map(|i| kinds[(i * 7 + i / 5) % kinds.len()]).collect();
+        Clipboard { size, blocks }
+    }
+
+    #[test]
+    fn four_quarter_turns_give_back_the_original() {
+        let clipboard = sample();
+        let turned = clipboard.rotate();
+        assert_eq!(turned.size, [4, 3, 2]);
+        // the block at the min corner moves to the max x edge
+        assert_eq!(turned.blocks[turned.index(3, 0, 0)], clipboard.blocks[clipboard.index(0, 0, 0)]);
+        let turned = turned.rotate().rotate().rotate();
+        assert_eq!(turned.size, clipboard.size);
+        assert_eq!(turned.blocks, clipboard.blocks);
+    }
+
+    #[test]
+    fn mirroring_twice_gives_back_the_original() {
+        let clipboard = sample();
+        for axis in 0..3 {
+            let mirrored = clipboard.mirror(axis);
+            assert_ne!(mirrored.blocks, clipboard.blocks);
+            let mirrored = mirrored.mirror(axis);
+            assert_eq!(mirrored.size, clipboard.size);
+            assert_eq!(mirrored.blocks, clipboard.blocks);
+        }
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let clipboard = sample();
+        let read_back = Clipboard::from_bytes(&clipboard.to_bytes().unwrap()).unwrap();
+        assert_eq!(read_back.size, clipboard.size);
+        assert_eq!(read_back.blocks, clipboard.blocks);
+
+        assert!(Clipboard::from_bytes(b"VXCB").is_err());
+        assert!(Clipboard::from_bytes(b"nope").is_err());
+    }
+
+    #[test]
+    fn long_block_names_are_rejected() {
+        let mut writer = ByteWriter::default();
+        assert!(write_name(&mut writer, &"a".repeat(255)).is_ok());
+        let error = write_name(&mut writer, &"a".repeat(256)).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,118 @@
+use crate::generation_params::{ContinentMask, GenerationParams};
+use crate::region::CHUNK_PER_ROW;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+// masks are laid over the whole region, in chunk units
+const MASK_RADIUS: f64 = CHUNK_PER_ROW as f64 / 2.0;
+
+fn smoothstep(t: f64) -> f64 {
+    let t = t.clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn radial_falloff(nx: f64, nz: f64, centre: [f64; 2], sharpness: f64) -> f64 {
+    let dx = (nx - centre[0]) / MASK_RADIUS;
+    let dz = (nz - centre[1]) / MASK_RADIUS;
+    let distance = (dx * dx + dz * dz).sqrt();
+    smoothstep((1.0 - distance) * sharpness)
+}
+
+// the mask centre plus voronoi cell centres and whether each one is land, they only depend on
+// the params and the region so the region builds them once and every height sample reuses them
+pub struct ContinentCells {
+    centre: [f64; 2],
+    points: Vec<([f64; 2], bool)>,
+}
+
+impl ContinentCells {
+    pub fn new(params: &GenerationParams, region_centre: [i32; 2]) -> Self {
+        // the middle of the region's chunks, which run from the low chunk's edge to the high chunk's far edge
+        let centre = region_centre.map(|chunk| {
+            let low = chunk - CHUNK_PER_ROW / 2;
+            let high = chunk + CHUNK_PER_ROW / 2 + 1;
+            (low + high) as f64 / 2.0
+        });
+        if params.continent_mask != ContinentMask::Voronoi {
+            return Self {
+                centre,
+                points: Vec::new(),
+            };
+        }
+        let mut rng = StdRng::seed_from_u64(params.seed as u64 ^ 0xC0A5_7A11);
+        let mut points: Vec<([f64; 2], bool)> = (0..params.continent_cells)
+            .map(|_| {
+                let x = centre[0] + rng.gen_range(-MASK_RADIUS..MASK_RADIUS);
+                let z = centre[1] + rng.gen_range(-MASK_RADIUS..MASK_RADIUS);
+                ([x, z], rng.gen_bool(0.5))
+            })
+            .collect();
+        if let Some(first) = points.first_mut() {
+            first.1 = true;
+        }
+        Self { centre, points }
+    }
+}
+
+fn voronoi_mask(nx: f64, nz: f64, params: &GenerationParams, cells: &ContinentCells) -> f64 {
+    let mut nearest_land = f64::MAX;
+    let mut nearest_ocean = f64::MAX;
+    for (point, is_land) in cells.points.iter().copied() {
+        let distance = ((nx - point[0]).powi(2) + (nz - point[1]).powi(2)).sqrt();
+        if is_land {
+            nearest_land = nearest_land.min(distance);
+        } else {
+            nearest_ocean = nearest_ocean.min(distance);
+        }
+    }
+    // positive on the land side of a cell border, growing towards the land cell centre
+    let border_distance = (nearest_ocean - nearest_land).min(MASK_RADIUS);
+    smoothstep(0.5 + border_distance * params.coast_sharpness)
+        * radial_falloff(nx, nz, cells.centre, params.coast_sharpness)
+}
+
+pub fn continent_mask(nx: f64, nz: f64, params: &GenerationParams, cells: &ContinentCells) -> f64 {
+    match params.continent_mask {
+        ContinentMask::None => 1.0,
+        ContinentMask::Island => radial_falloff(nx, nz, cells.centre, params.coast_sharpness),
+        ContinentMask::Voronoi => voronoi_mask(nx, nz, params, cells),
+    }
+}
+
+pub fn apply_continent_mask(
+    height: f64,
+    nx: f64,
+    nz: f64,
+    params: &GenerationParams,
+    cells: &ContinentCells,
+) -> f64 {
+    if params.continent_mask == ContinentMask::None {
+        return height;
+    }
+    let ocean_floor = (params.water_level as f64 - params.ocean_depth as f64).max(1.0);
+    ocean_floor + (height - ocean_floor) * continent_mask(nx, nz, params, cells)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mask_follows_the_region_centre() {
+        let params = GenerationParams {
+            continent_mask: ContinentMask::Island,
+            ..GenerationParams::default()
+        };
+        let origin = ContinentCells::new(&params, [0, 0]);
+        let shifted = ContinentCells::new(&params, [5, -3]);
+        assert_eq!(continent_mask(0.5, 0.5, &params, &origin), 1.0);
+        assert_eq!(continent_mask(5.5, -2.5, &params, &shifted), 1.0);
+        // the same spot relative to each region gets the same mask
+        for (dx, dz) in [(-1.5, -1.5), (1.2, 0.3), (0.0, 1.4)] {
+            let expected = continent_mask(0.5 + dx, 0.5 + dz, &params, &origin);
+            assert!((continent_mask(5.5 + dx, -2.5 + dz, &params, &shifted) - expected).abs() < 1e-9);
+        }
+        assert!(continent_mask(-1.0, -1.0, &params, &shifted) < 1e-6);
+    }
+}
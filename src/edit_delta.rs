@@ -0,0 +1,71 @@
+use crate::chunk::Chunk;
+use crate::fluid::FULL_FLUID_LEVEL;
+use crate::region::Region;
+use crate::voxel::{BlockType, Voxel};
+
+use std::collections::HashMap;
+
+// voxels changed after generation, keyed by chunk position and then by linear index in the chunk
+#[derive(Default)]
+pub struct EditDelta {
+    chunks: HashMap<[i32; 2], HashMap<usize, BlockType>>,
+    // levels of flowing fluid, sources have no entry
+    fluid_levels: HashMap<[i32; 2], HashMap<usize, u8>>,
+}
+
+impl EditDelta {
+    pub fn record(&mut self, chunk_position: [i32; 2], index: usize, block_type: BlockType) {
+        self.chunks
+            .entry(chunk_position)
+            .or_default()
+            .insert(index, block_type);
+        if let Some(levels) = self.fluid_levels.get_mut(&chunk_position) {
+            levels.remove(&index);
+        }
+    }
+
+    pub fn record_fluid_level(&mut self, chunk_position: [i32; 2], index: usize, level: u8) {
+        let levels = self.fluid_levels.entry(chunk_position).or_default();
+        if level >= FULL_FLUID_LEVEL {
+            levels.remove(&index);
+        } else {
+            levels.insert(index, level);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.values().map(|edits| edits.len()).sum()
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = (&[i32; 2], &HashMap<usize, BlockType>)> {
+        self.chunks.iter()
+    }
+
+    pub fn fluid_levels(&self) -> impl Iterator<Item = (&[i32; 2], &HashMap<usize, u8>)> {
+        self.fluid_levels.iter().filter(|(_, levels)| !levels.is_empty())
+    }
+
+    // writes every recorded edit into freshly generated chunks of the region
+    pub fn replay(&self, region: &mut Region) {
+        for (chunk_position, edits) in self.chunks.iter() {
+            let Some(chunk_index) = region.chunk_index(chunk_position[0], chunk_position[1]) else {
+                continue;
+            };
+            let chunk = &mut region.chunk_buffer[chunk_index];
+            for (index, block_type) in edits.iter() {
+                let [x, y, z] = Chunk::delinearize(*index);
+                chunk.set_voxel(x, y, z, Voxel::new(*block_type));
+            }
+        }
+        for (chunk_position, levels) in self.fluid_levels.iter() {
+            let Some(chunk_index) = region.chunk_index(chunk_position[0], chunk_position[1]) else {
+                continue;
+            };
+            let chunk = &mut region.chunk_buffer[chunk_index];
+            for (index, level) in levels.iter() {
+                let [x, y, z] = Chunk::delinearize(*index);
+                chunk.set_fluid_level(x, y, z, *level);
+            }
+        }
+    }
+}
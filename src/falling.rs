@@ -0,0 +1,82 @@
+use crate::chunk::{Chunk, CHUNK_SIZE};
+use crate::fluid::FULL_FLUID_LEVEL;
+use crate::region::Region;
+use crate::voxel::{BlockType, Voxel};
+
+use std::collections::HashSet;
+
+// one voxel of fall per tick
+pub const TICK_INTERVAL: instant::Duration = instant::Duration::from_millis(50);
+
+// granular blocks come to rest on anything that stops the player, air and fluids let them through
+fn supports(block_type: BlockType) -> bool {
+    block_type.definition().collidable
+}
+
+// drops unsupported granular blocks of a freshly generated chunk straight down before it is ever shown,
+// chunks span the full height so the bottom of a column is the world floor
+pub fn settle_generated(blocks_vector: &mut [Voxel]) {
+    for x in 0..CHUNK_SIZE {
+        for z in 0..CHUNK_SIZE {
+            // each gap between two supporting blocks that are not granular themselves settles on its own
+            let mut start = 0;
+            for y in 0..=CHUNK_SIZE {
+                if y < CHUNK_SIZE {
+                    let block_type = blocks_vector[Chunk::linearize(x, y, z)].block_type;
+                    if block_type.definition().granular || !supports(block_type) {
+                        continue;
+                    }
+                }
+                let gap_start = start;
+                start = y + 1;
+                let gap: Vec<Voxel> = (gap_start..y).map(|y| blocks_vector[Chunk::linearize(x, y, z)]).collect();
+                if !gap.iter().any(|voxel| voxel.block_type.definition().granular) {
+                    continue;
+                }
+                // granular blocks sink to the bottom, the air and fluid they fell through keep their order above
+                let (granular, rest): (Vec<Voxel>, Vec<Voxel>) =
+                    gap.into_iter().partition(|voxel| voxel.block_type.definition().granular);
+                for (offset, voxel) in granular.into_iter().chain(rest).enumerate() {
+                    blocks_vector[Chunk::linearize(x, gap_start + offset, z)] = voxel;
+                }
+            }
+        }
+    }
+}
+
+// moves every unsupported granular block touched since the last tick down by one, returns whether any fell
+pub fn tick(region: &mut Region) -> bool {
+    let updates = std::mem::take(&mut region.falling_updates);
+    let mut candidates: HashSet<[i32; 3]> = HashSet::new();
+    for position in updates {
+        candidates.insert(position);
+        candidates.insert([position[0], position[1] + 1, position[2]]);
+    }
+    // lowest first so a whole stack falls together instead of waiting on the block below
+    let mut candidates: Vec<[i32; 3]> = candidates.into_iter().collect();
+    candidates.sort_by_key(|position| position[1]);
+    let mut changed = false;
+    for position in candidates {
+        let Some(block_type) = region.get_block(position).filter(|block_type| block_type.definition().granular) else {
+            continue;
+        };
+        let below = [position[0], position[1] - 1, position[2]];
+        // nothing below y = 0, the world floor holds everything up
+        let Some(below_block) = region.get_block(below) else {
+            continue;
+        };
+        if supports(below_block) {
+            continue;
+        }
+        // fluid the block sinks into moves up into the space it left instead of disappearing
+        let displaced_level = region.get_fluid_level(below).unwrap_or(FULL_FLUID_LEVEL);
+        region.set_block_untracked(below, block_type);
+        if below_block.definition().fluid {
+            region.set_fluid(position, below_block, displaced_level);
+        } else {
+            region.set_block_untracked(position, BlockType::AIR);
+        }
+        changed = true;
+    }
+    changed
+}
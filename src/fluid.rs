@@ -0,0 +1,83 @@
+use crate::region::Region;
+use crate::voxel::BlockType;
+
+use std::collections::HashSet;
+
+// sources hold the full level, every voxel of sideways flow loses one
+pub const FULL_FLUID_LEVEL: u8 = 8;
+pub const TICK_INTERVAL: instant::Duration = instant::Duration::from_millis(250);
+
+const NEIGHBOURS: [[i32; 3]; 6] = [[1, 0, 0], [-1, 0, 0], [0, 1, 0], [0, -1, 0], [0, 0, 1], [0, 0, -1]];
+const HORIZONTAL: [[i32; 3]; 4] = [[1, 0, 0], [-1, 0, 0], [0, 0, 1], [0, 0, -1]];
+
+fn offset(position: [i32; 3], step: [i32; 3]) -> [i32; 3] {
+    [position[0] + step[0], position[1] + step[1], position[2] + step[2]]
+}
+
+// air and anything else fluid can flow into
+fn replaceable(block_type: BlockType) -> bool {
+    let definition = block_type.definition();
+    !definition.collidable && !definition.fluid
+}
+
+// what the voxel should become this tick, None when it stays as it is
+fn next_state(region: &Region, position: [i32; 3]) -> Option<(BlockType, u8)> {
+    let current = region.get_block(position)?;
+    let is_fluid = current.definition().fluid;
+    let level = region.get_fluid_level(position)?;
+    if (is_fluid && level == FULL_FLUID_LEVEL) || (!is_fluid && !replaceable(current)) {
+        return None;
+    }
+    let mut fed: Option<(BlockType, u8)> = None;
+    let above = region.get_block(offset(position, [0, 1, 0]));
+    if let Some(above) = above.filter(|block_type| block_type.definition().fluid) {
+        // falling fluid stays nearly full so it spreads out again where it lands
+        fed = Some((above, FULL_FLUID_LEVEL - 1));
+    } else {
+        for step in HORIZONTAL {
+            let neighbour = offset(position, step);
+            let Some(block_type) = region.get_block(neighbour).filter(|block_type| block_type.definition().fluid) else {
+                continue;
+            };
+            let neighbour_level = region.get_fluid_level(neighbour)?;
+            // fluid only spreads sideways once it cannot fall, so it rests on something solid or on a source
+            let below = offset(neighbour, [0, -1, 0]);
+            let resting = region.get_block(below).is_some_and(|below_block| {
+                !replaceable(below_block)
+                    && (!below_block.definition().fluid || region.get_fluid_level(below) == Some(FULL_FLUID_LEVEL))
+            });
+            if resting && neighbour_level > 1 && fed.is_none_or(|(_, fed_level)| neighbour_level - 1 > fed_level) {
+                fed = Some((block_type, neighbour_level - 1));
+            }
+        }
+    }
+    match fed {
+        Some((block_type, fed_level)) if block_type != current || fed_level != level => Some((block_type, fed_level)),
+        Some(_) => None,
+        // flow that lost every source around it dries up
+        None if is_fluid => Some((BlockType::AIR, FULL_FLUID_LEVEL)),
+        None => None,
+    }
+}
+
+// one simulation step over everything that changed since the last one, returns whether anything moved
+pub fn tick(region: &mut Region) -> bool {
+    let updates = std::mem::take(&mut region.fluid_updates);
+    let mut candidates: HashSet<[i32; 3]> = HashSet::new();
+    for position in updates {
+        candidates.insert(position);
+        for step in NEIGHBOURS {
+            candidates.insert(offset(position, step));
+        }
+    }
+    // every voxel decides from the state before the tick so the update order does not matter
+    let changes: Vec<([i32; 3], (BlockType, u8))> = candidates
+        .into_iter()
+        .filter_map(|position| next_state(region, position).map(|state| (position, state)))
+        .collect();
+    let mut changed = false;
+    for (position, (block_type, level)) in changes {
+        changed |= region.set_fluid(position, block_type, level);
+    }
+    changed
+}
@@ -1,4 +1,19 @@
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum GenerationMode {
+    Terrain,
+    FloatingIslands,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ContinentMask {
+    None,
+    Voronoi,
+    Island,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub struct GenerationParams {
+    pub mode: GenerationMode,
     pub seed: u32,
     pub octaves: usize,
     pub frequency: f64,
@@ -6,4 +21,55 @@ pub struct GenerationParams {
     pub water_level: u32,
     pub noise_multiplier: f64,
     pub dirt_layer_height: u32,
+    pub bedrock_thickness: u32,
+    pub strata_thickness: u32,
+    pub strata_warp: f64,
+    pub continent_mask: ContinentMask,
+    pub continent_cells: u32,
+    pub coast_sharpness: f64,
+    pub ocean_depth: u32,
+    pub cave_worm_count: u32,
+    pub cave_worm_length: u32,
+    pub cave_worm_radius: f64,
+    pub cave_min_y: u32,
+    pub cave_max_y: u32,
+    pub cave_openings: u32,
+    pub structures: bool,
+    pub island_frequency: f64,
+    pub island_height: u32,
+    pub island_thickness: u32,
+    pub island_density: f64,
+}
+
+impl Default for GenerationParams {
+    fn default() -> Self {
+        GenerationParams {
+            mode: GenerationMode::Terrain,
+            seed: 2,
+            octaves: 2,
+            frequency: 2.0,
+            ground_level: 20,
+            water_level: 10,
+            noise_multiplier: 20.0,
+            dirt_layer_height: 2,
+            bedrock_thickness: 3,
+            strata_thickness: 4,
+            strata_warp: 3.0,
+            continent_mask: ContinentMask::None,
+            continent_cells: 12,
+            coast_sharpness: 4.0,
+            ocean_depth: 6,
+            cave_worm_count: 2,
+            cave_worm_length: 80,
+            cave_worm_radius: 2.5,
+            cave_min_y: 4,
+            cave_max_y: 24,
+            cave_openings: 1,
+            structures: true,
+            island_frequency: 3.0,
+            island_height: 40,
+            island_thickness: 12,
+            island_density: 0.1,
+        }
+    }
 }
@@ -5,12 +5,75 @@ use wgpu::{CommandEncoder, Device, Queue, StoreOp, TextureFormat, TextureView};
 use winit::event::WindowEvent;
 use winit::window::Window;
 
-use crate::generation_params::GenerationParams;
+use crate::block_registry::BlockRegistry;
+use crate::brush::{Brush, BrushSettings};
+use crate::clipboard::Selection;
+use crate::generation_params::{ContinentMask, GenerationMode, GenerationParams};
+use crate::history::DEFAULT_HISTORY_DEPTH;
+use crate::operations::Operation;
+use crate::voxel::{BlockType, Voxel};
+
+// what the mouse buttons do in the world
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Tool {
+    Build,
+    Select,
+    Brush,
+}
+
+fn block_combo(ui: &mut Ui, label: &str, selected: &mut BlockType, include_air: bool) {
+    egui::ComboBox::from_label(label)
+        .selected_text(Voxel::get_name_for_type(*selected))
+        .show_ui(ui, |ui| {
+            for definition in BlockRegistry::global()
+                .iter()
+                .filter(|definition| include_air || definition.id != BlockType::AIR.id())
+            {
+                ui.selectable_value(selected, BlockType(definition.id), &definition.name);
+            }
+        });
+}
 
 pub struct GuiRenderer {
     state: State,
     renderer: Renderer,
     pub updated: bool,
+    pub world_path: String,
+    pub save_requested: bool,
+    pub load_requested: bool,
+    pub model_path: String,
+    pub import_requested: bool,
+    pub export_requested: bool,
+    pub mesh_path: String,
+    pub schematic_path: String,
+    pub schematic_version: i32,
+    pub schematic_import_requested: bool,
+    pub schematic_export_requested: bool,
+    pub mesh_export_requested: bool,
+    pub status: String,
+    // block under the cursor, filled in every frame from the pick ray
+    pub pointed_at: String,
+    pub selected_block: BlockType,
+    pub history_depth: usize,
+    pub undo_requested: bool,
+    pub redo_requested: bool,
+    pub tool: Tool,
+    pub selection: Selection,
+    pub skip_air: bool,
+    pub copy_requested: bool,
+    pub rotate_requested: bool,
+    pub mirror_requested: Option<usize>,
+    pub paste_requested: bool,
+    pub clipboard_path: String,
+    pub clipboard_save_requested: bool,
+    pub clipboard_load_requested: bool,
+    pub replaced_block: BlockType,
+    pub operation_requested: Option<Operation>,
+    pub brush: Brush,
+    pub brush_radius: f32,
+    pub brush_strength: f32,
+    pub brush_spherical: bool,
+    pub mode: GenerationMode,
     pub seed: u32,
     pub octaves: usize,
     pub frequency: f64,
@@ -18,6 +81,24 @@ pub struct GuiRenderer {
     pub water_level: u32,
     pub noise_multiplier: f64,
     pub dirt_layer_height: u32,
+    pub bedrock_thickness: u32,
+    pub strata_thickness: u32,
+    pub strata_warp: f64,
+    pub continent_mask: ContinentMask,
+    pub continent_cells: u32,
+    pub coast_sharpness: f64,
+    pub ocean_depth: u32,
+    pub cave_worm_count: u32,
+    pub cave_worm_length: u32,
+    pub cave_worm_radius: f64,
+    pub cave_min_y: u32,
+    pub cave_max_y: u32,
+    pub cave_openings: u32,
+    pub structures: bool,
+    pub island_frequency: f64,
+    pub island_height: u32,
+    pub island_thickness: u32,
+    pub island_density: f64,
 }
 
 impl GuiRenderer {
@@ -48,21 +129,76 @@ impl GuiRenderer {
             msaa_samples,
         );
 
+        let defaults = GenerationParams::default();
         GuiRenderer {
             state: egui_state,
             renderer: egui_renderer,
             updated: false,
-            seed: 2,
-            octaves: 2,
-            frequency: 2.0,
-            ground_level: 20,
-            water_level: 10,
-            noise_multiplier: 20.0,
-            dirt_layer_height: 2,
+            world_path: String::from("world"),
+            save_requested: false,
+            load_requested: false,
+            model_path: String::from("model.vox"),
+            import_requested: false,
+            export_requested: false,
+            mesh_path: String::from("terrain.glb"),
+            schematic_path: String::from("build.schem"),
+            schematic_version: 3,
+            schematic_import_requested: false,
+            schematic_export_requested: false,
+            mesh_export_requested: false,
+            status: String::new(),
+            pointed_at: String::new(),
+            selected_block: BlockType::STONE,
+            history_depth: DEFAULT_HISTORY_DEPTH,
+            undo_requested: false,
+            redo_requested: false,
+            tool: Tool::Build,
+            selection: Selection::default(),
+            skip_air: false,
+            copy_requested: false,
+            rotate_requested: false,
+            mirror_requested: None,
+            paste_requested: false,
+            clipboard_path: String::from("clipboard.vxc"),
+            clipboard_save_requested: false,
+            clipboard_load_requested: false,
+            replaced_block: BlockType::DIRT,
+            operation_requested: None,
+            brush: Brush::Raise,
+            brush_radius: 4.0,
+            brush_strength: 1.0,
+            brush_spherical: false,
+            mode: defaults.mode,
+            seed: defaults.seed,
+            octaves: defaults.octaves,
+            frequency: defaults.frequency,
+            ground_level: defaults.ground_level,
+            water_level: defaults.water_level,
+            noise_multiplier: defaults.noise_multiplier,
+            dirt_layer_height: defaults.dirt_layer_height,
+            bedrock_thickness: defaults.bedrock_thickness,
+            strata_thickness: defaults.strata_thickness,
+            strata_warp: defaults.strata_warp,
+            continent_mask: defaults.continent_mask,
+            continent_cells: defaults.continent_cells,
+            coast_sharpness: defaults.coast_sharpness,
+            ocean_depth: defaults.ocean_depth,
+            cave_worm_count: defaults.cave_worm_count,
+            cave_worm_length: defaults.cave_worm_length,
+            cave_worm_radius: defaults.cave_worm_radius,
+            cave_min_y: defaults.cave_min_y,
+            cave_max_y: defaults.cave_max_y,
+            cave_openings: defaults.cave_openings,
+            structures: defaults.structures,
+            island_frequency: defaults.island_frequency,
+            island_height: defaults.island_height,
+            island_thickness: defaults.island_thickness,
+            island_density: defaults.island_density,
         }
     }
     pub fn get_generation_params(&self) -> GenerationParams {
         GenerationParams {
+            mode: self.mode,
             seed: self.seed,
             octaves: self.octaves,
             frequency: self.frequency,
@@ -70,11 +206,69 @@ impl GuiRenderer {
             water_level: self.water_level,
             noise_multiplier: self.noise_multiplier,
             dirt_layer_height: self.dirt_layer_height,
+            bedrock_thickness: self.bedrock_thickness,
+            strata_thickness: self.strata_thickness,
+            strata_warp: self.strata_warp,
+            continent_mask: self.continent_mask,
+            continent_cells: self.continent_cells,
+            coast_sharpness: self.coast_sharpness,
+            ocean_depth: self.ocean_depth,
+            cave_worm_count: self.cave_worm_count,
+            cave_worm_length: self.cave_worm_length,
+            cave_worm_radius: self.cave_worm_radius,
+            cave_min_y: self.cave_min_y,
+            cave_max_y: self.cave_max_y,
+            cave_openings: self.cave_openings,
+            structures: self.structures,
+            island_frequency: self.island_frequency,
+            island_height: self.island_height,
+            island_thickness: self.island_thickness,
+            island_density: self.island_density,
+        }
+    }
+
+    pub fn set_generation_params(&mut self, generation_params: &GenerationParams) {
+        self.mode = generation_params.mode;
+        self.seed = generation_params.seed;
+        self.octaves = generation_params.octaves;
+        self.frequency = generation_params.frequency;
+        self.ground_level = generation_params.ground_level;
+        self.water_level = generation_params.water_level;
+        self.noise_multiplier = generation_params.noise_multiplier;
+        self.dirt_layer_height = generation_params.dirt_layer_height;
+        self.bedrock_thickness = generation_params.bedrock_thickness;
+        self.strata_thickness = generation_params.strata_thickness;
+        self.strata_warp = generation_params.strata_warp;
+        self.continent_mask = generation_params.continent_mask;
+        self.continent_cells = generation_params.continent_cells;
+        self.coast_sharpness = generation_params.coast_sharpness;
+        self.ocean_depth = generation_params.ocean_depth;
+        self.cave_worm_count = generation_params.cave_worm_count;
+        self.cave_worm_length = generation_params.cave_worm_length;
+        self.cave_worm_radius = generation_params.cave_worm_radius;
+        self.cave_min_y = generation_params.cave_min_y;
+        self.cave_max_y = generation_params.cave_max_y;
+        self.cave_openings = generation_params.cave_openings;
+        self.structures = generation_params.structures;
+        self.island_frequency = generation_params.island_frequency;
+        self.island_height = generation_params.island_height;
+        self.island_thickness = generation_params.island_thickness;
+        self.island_density = generation_params.island_density;
+    }
+
+    pub fn get_brush_settings(&self) -> BrushSettings {
+        BrushSettings {
+            brush: self.brush,
+            radius: self.brush_radius,
+            strength: self.brush_strength,
+            spherical: self.brush_spherical,
+            paint_block: self.selected_block,
         }
     }
 
-    pub fn handle_input(&mut self, window: &Window, event: &WindowEvent) {
-        self.state.on_window_event(window, &event);
+    // returns whether egui used the event, so clicks on a window do not edit the world
+    pub fn handle_input(&mut self, window: &Window, event: &WindowEvent) -> bool {
+        self.state.on_window_event(window, event).consumed
     }
 
     pub fn draw(
@@ -104,6 +298,18 @@ impl GuiRenderer {
                             .add(egui::TextEdit::singleline(&mut raw_seed_input).hint_text("Seed"));
                         self.seed = raw_seed_input.parse().unwrap();
                         ui.separator();
+                        ui.label("Mode:");
+                        responses.push(ui.radio_value(
+                            &mut self.mode,
+                            GenerationMode::Terrain,
+                            "Terrain",
+                        ));
+                        responses.push(ui.radio_value(
+                            &mut self.mode,
+                            GenerationMode::FloatingIslands,
+                            "Floating islands",
+                        ));
+                        ui.separator();
                         ui.label("Octaves:");
                         responses.push(
                             ui.add(egui::Slider::new(&mut self.octaves, 1..=10).text("Octaves")),
@@ -139,10 +345,273 @@ impl GuiRenderer {
                                     .text("Dirt layer height"),
                             ),
                         );
+                        ui.separator();
+                        ui.label("Bedrock thickness:");
+                        responses.push(ui.add(
+                            egui::Slider::new(&mut self.bedrock_thickness, 1..=8)
+                                .text("Bedrock thickness"),
+                        ));
+                        ui.separator();
+                        ui.label("Strata thickness:");
+                        responses.push(ui.add(
+                            egui::Slider::new(&mut self.strata_thickness, 1..=12)
+                                .text("Strata thickness"),
+                        ));
+                        ui.separator();
+                        ui.label("Strata warp:");
+                        responses.push(ui.add(
+                            egui::Slider::new(&mut self.strata_warp, 0.0..=10.0).text("Strata warp"),
+                        ));
+                        ui.separator();
+                        ui.label("Continent mask:");
+                        responses.push(ui.radio_value(
+                            &mut self.continent_mask,
+                            ContinentMask::None,
+                            "None",
+                        ));
+                        responses.push(ui.radio_value(
+                            &mut self.continent_mask,
+                            ContinentMask::Voronoi,
+                            "Archipelago",
+                        ));
+                        responses.push(ui.radio_value(
+                            &mut self.continent_mask,
+                            ContinentMask::Island,
+                            "Island",
+                        ));
+                        if self.continent_mask != ContinentMask::None {
+                            if self.continent_mask == ContinentMask::Voronoi {
+                                responses.push(ui.add(
+                                    egui::Slider::new(&mut self.continent_cells, 1..=64)
+                                        .text("Cell count"),
+                                ));
+                            }
+                            responses.push(ui.add(
+                                egui::Slider::new(&mut self.coast_sharpness, 0.5..=16.0)
+                                    .text("Coast sharpness"),
+                            ));
+                            responses.push(ui.add(
+                                egui::Slider::new(&mut self.ocean_depth, 0..=16).text("Ocean depth"),
+                            ));
+                        }
+                        ui.separator();
+                        ui.label("Cave tunnels per chunk:");
+                        responses.push(ui.add(
+                            egui::Slider::new(&mut self.cave_worm_count, 0..=8).text("Tunnels"),
+                        ));
+                        ui.separator();
+                        ui.label("Cave tunnel length:");
+                        responses.push(ui.add(
+                            egui::Slider::new(&mut self.cave_worm_length, 8..=128).text("Length"),
+                        ));
+                        ui.separator();
+                        ui.label("Cave tunnel radius:");
+                        responses.push(ui.add(
+                            egui::Slider::new(&mut self.cave_worm_radius, 1.0..=6.0).text("Radius"),
+                        ));
+                        ui.separator();
+                        ui.label("Cave depth range:");
+                        responses.push(ui.add(
+                            egui::Slider::new(&mut self.cave_min_y, 1..=62).text("Min y"),
+                        ));
+                        responses.push(ui.add(
+                            egui::Slider::new(&mut self.cave_max_y, 1..=62).text("Max y"),
+                        ));
+                        ui.separator();
+                        ui.label("Cave openings per chunk:");
+                        responses.push(ui.add(
+                            egui::Slider::new(&mut self.cave_openings, 0..=4).text("Openings"),
+                        ));
+                        ui.separator();
+                        responses.push(ui.checkbox(&mut self.structures, "Place structures"));
+                        if self.mode == GenerationMode::FloatingIslands {
+                            ui.separator();
+                            ui.label("Island frequency:");
+                            responses.push(ui.add(
+                                egui::Slider::new(&mut self.island_frequency, 1.0..=10.0)
+                                    .text("Island frequency"),
+                            ));
+                            ui.separator();
+                            ui.label("Island height:");
+                            responses.push(ui.add(
+                                egui::Slider::new(&mut self.island_height, 8..=56)
+                                    .text("Island height"),
+                            ));
+                            ui.separator();
+                            ui.label("Island thickness:");
+                            responses.push(ui.add(
+                                egui::Slider::new(&mut self.island_thickness, 2..=24)
+                                    .text("Island thickness"),
+                            ));
+                            ui.separator();
+                            ui.label("Island density:");
+                            responses.push(ui.add(
+                                egui::Slider::new(&mut self.island_density, -0.5..=0.5)
+                                    .text("Island density"),
+                            ));
+                        }
                         if responses.iter().any(|x| x.changed()) {
                             self.updated = true;
                         }
                     });
+                egui::Window::new("World")
+                    .resizable(false)
+                    .default_open(false)
+                    .show(ctx, |ui| {
+                        ui.label("World directory:");
+                        ui.add(egui::TextEdit::singleline(&mut self.world_path));
+                        ui.horizontal(|ui| {
+                            if ui.button("Save").clicked() {
+                                self.save_requested = true;
+                            }
+                            if ui.button("Load").clicked() {
+                                self.load_requested = true;
+                            }
+                        });
+                        ui.separator();
+                        ui.label("MagicaVoxel model:");
+                        ui.add(egui::TextEdit::singleline(&mut self.model_path));
+                        ui.horizontal(|ui| {
+                            if ui.button("Import at cursor").clicked() {
+                                self.import_requested = true;
+                            }
+                            if ui.button("Export region").clicked() {
+                                self.export_requested = true;
+                            }
+                        });
+                        ui.separator();
+                        ui.label("Mesh file (.obj, .ply or .glb):");
+                        ui.add(egui::TextEdit::singleline(&mut self.mesh_path));
+                        if ui.button("Export mesh").clicked() {
+                            self.mesh_export_requested = true;
+                        }
+                        ui.separator();
+                        ui.label("Sponge schematic:");
+                        ui.add(egui::TextEdit::singleline(&mut self.schematic_path));
+                        ui.horizontal(|ui| {
+                            ui.radio_value(&mut self.schematic_version, 2, "Version 2");
+                            ui.radio_value(&mut self.schematic_version, 3, "Version 3");
+                        });
+                        ui.horizontal(|ui| {
+                            if ui.button("Import at cursor").clicked() {
+                                self.schematic_import_requested = true;
+                            }
+                            if ui.button("Export region").clicked() {
+                                self.schematic_export_requested = true;
+                            }
+                        });
+                        if !self.status.is_empty() {
+                            ui.label(&self.status);
+                        }
+                    });
+                egui::Window::new("Edit")
+                    .resizable(false)
+                    .default_open(false)
+                    .show(ctx, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.radio_value(&mut self.tool, Tool::Build, "Build");
+                            ui.radio_value(&mut self.tool, Tool::Select, "Select");
+                            ui.radio_value(&mut self.tool, Tool::Brush, "Brush");
+                        });
+                        match self.tool {
+                            Tool::Build => ui.label("Left click removes, right click places:"),
+                            Tool::Select => ui.label("Left click picks the first corner, right click the second:"),
+                            Tool::Brush => ui.label("Hold the left button to sculpt, paint uses the selected block:"),
+                        };
+                        if !self.pointed_at.is_empty() {
+                            ui.label(format!("Pointing at {}", self.pointed_at));
+                        }
+                        if self.tool == Tool::Brush {
+                            ui.horizontal_wrapped(|ui| {
+                                for brush in Brush::ALL {
+                                    ui.radio_value(&mut self.brush, brush, brush.name());
+                                }
+                            });
+                            ui.add(egui::Slider::new(&mut self.brush_radius, 1.0..=16.0).text("Radius"));
+                            ui.add(egui::Slider::new(&mut self.brush_strength, 0.1..=4.0).text("Strength"));
+                            ui.checkbox(&mut self.brush_spherical, "Spherical falloff");
+                        }
+                        block_combo(ui, "Block", &mut self.selected_block, false);
+                        ui.separator();
+                        ui.horizontal(|ui| {
+                            if ui.button("Undo (Ctrl+Z)").clicked() {
+                                self.undo_requested = true;
+                            }
+                            if ui.button("Redo (Ctrl+Y)").clicked() {
+                                self.redo_requested = true;
+                            }
+                        });
+                        ui.add(
+                            egui::Slider::new(&mut self.history_depth, 1..=1000)
+                                .text("History depth"),
+                        );
+                        ui.separator();
+                        let describe = |corner: Option<[i32; 3]>| {
+                            corner.map_or(String::from("-"), |corner| {
+                                format!("{} {} {}", corner[0], corner[1], corner[2])
+                            })
+                        };
+                        ui.label(format!(
+                            "Selection: {} to {}",
+                            describe(self.selection.first),
+                            describe(self.selection.second)
+                        ));
+                        ui.horizontal(|ui| {
+                            if ui.button("Copy").clicked() {
+                                self.copy_requested = true;
+                            }
+                            if ui.button("Clear").clicked() {
+                                self.selection = Selection::default();
+                            }
+                        });
+                        ui.label("Clipboard:");
+                        ui.horizontal(|ui| {
+                            if ui.button("Rotate 90°").clicked() {
+                                self.rotate_requested = true;
+                            }
+                            if ui.button("Mirror X").clicked() {
+                                self.mirror_requested = Some(0);
+                            }
+                            if ui.button("Mirror Y").clicked() {
+                                self.mirror_requested = Some(1);
+                            }
+                            if ui.button("Mirror Z").clicked() {
+                                self.mirror_requested = Some(2);
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            if ui.button("Paste at cursor").clicked() {
+                                self.paste_requested = true;
+                            }
+                            ui.checkbox(&mut self.skip_air, "Skip air");
+                        });
+                        ui.add(egui::TextEdit::singleline(&mut self.clipboard_path));
+                        ui.horizontal(|ui| {
+                            if ui.button("Save clipboard").clicked() {
+                                self.clipboard_save_requested = true;
+                            }
+                            if ui.button("Load clipboard").clicked() {
+                                self.clipboard_load_requested = true;
+                            }
+                        });
+                        if !self.status.is_empty() {
+                            ui.label(&self.status);
+                        }
+                    });
+                egui::Window::new("Operations")
+                    .resizable(false)
+                    .default_open(false)
+                    .show(ctx, |ui| {
+                        ui.label("Applied to the selection with the block from the Edit window.");
+                        block_combo(ui, "Replaced block", &mut self.replaced_block, true);
+                        ui.horizontal_wrapped(|ui| {
+                            for operation in Operation::ALL {
+                                if ui.button(operation.name()).clicked() {
+                                    self.operation_requested = Some(operation);
+                                }
+                            }
+                        });
+                    });
             });
 
         self.state
@@ -0,0 +1,114 @@
+use crate::voxel::BlockType;
+
+use std::collections::VecDeque;
+
+pub const DEFAULT_HISTORY_DEPTH: usize = 100;
+
+#[derive(Copy, Clone)]
+struct BlockChange {
+    position: [i32; 3],
+    before: BlockType,
+    after: BlockType,
+}
+
+// one undo step, a single block edit or every change of a bulk operation or stroke
+#[derive(Default)]
+pub struct EditCommand {
+    changes: Vec<BlockChange>,
+}
+
+impl EditCommand {
+    // later changes are reverted first so overlapping writes restore the original block
+    pub fn undo_changes(&self) -> impl Iterator<Item = ([i32; 3], BlockType)> + '_ {
+        self.changes
+            .iter()
+            .rev()
+            .map(|change| (change.position, change.before))
+    }
+
+    pub fn redo_changes(&self) -> impl Iterator<Item = ([i32; 3], BlockType)> + '_ {
+        self.changes
+            .iter()
+            .map(|change| (change.position, change.after))
+    }
+}
+
+pub struct EditHistory {
+    undo_stack: VecDeque<EditCommand>,
+    redo_stack: Vec<EditCommand>,
+    depth: usize,
+    // changes recorded while a group is open end up in one command
+    group: Option<EditCommand>,
+    group_nesting: usize,
+}
+
+impl EditHistory {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            undo_stack: VecDeque::new(),
+            redo_stack: Vec::new(),
+            depth,
+            group: None,
+            group_nesting: 0,
+        }
+    }
+
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        while self.undo_stack.len() > self.depth {
+            self.undo_stack.pop_front();
+        }
+    }
+
+    // groups nest, so a bulk fill inside a brush stroke still undoes with the whole stroke
+    pub fn begin_group(&mut self) {
+        self.group_nesting += 1;
+    }
+
+    pub fn end_group(&mut self) {
+        self.group_nesting = self.group_nesting.saturating_sub(1);
+        if self.group_nesting == 0 {
+            if let Some(command) = self.group.take() {
+                self.push(command);
+            }
+        }
+    }
+
+    pub fn record(&mut self, position: [i32; 3], before: BlockType, after: BlockType) {
+        let change = BlockChange {
+            position,
+            before,
+            after,
+        };
+        if self.group_nesting > 0 {
+            self.group.get_or_insert_with(EditCommand::default).changes.push(change);
+        } else {
+            self.push(EditCommand {
+                changes: vec![change],
+            });
+        }
+    }
+
+    pub fn pop_undo(&mut self) -> Option<EditCommand> {
+        self.undo_stack.pop_back()
+    }
+
+    pub fn pop_redo(&mut self) -> Option<EditCommand> {
+        self.redo_stack.pop()
+    }
+
+    pub fn push_redo(&mut self, command: EditCommand) {
+        self.redo_stack.push(command);
+    }
+
+    // puts a redone command back without dropping the rest of the redo stack
+    pub fn push_undo(&mut self, command: EditCommand) {
+        self.undo_stack.push_back(command);
+        self.set_depth(self.depth);
+    }
+
+    fn push(&mut self, command: EditCommand) {
+        self.redo_stack.clear();
+        self.push_undo(command);
+    }
+}
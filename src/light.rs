@@ -0,0 +1,266 @@
+use crate::block_registry::{BlockDefinition, Opacity};
+use crate::chunk::CHUNK_SIZE;
+use crate::region::Region;
+
+use std::collections::VecDeque;
+
+pub const MAX_LIGHT: u8 = 15;
+// darkest a face gets, so caves stay readable instead of going black
+const MIN_BRIGHTNESS: f32 = 0.1;
+
+const NEIGHBOURS: [[i32; 3]; 6] = [[1, 0, 0], [-1, 0, 0], [0, 1, 0], [0, -1, 0], [0, 0, 1], [0, 0, -1]];
+const HORIZONTAL: [[i32; 3]; 4] = [[1, 0, 0], [-1, 0, 0], [0, 0, 1], [0, 0, -1]];
+
+// skylight and the red, green and blue block light spread independently of each other
+#[derive(Copy, Clone, PartialEq)]
+enum Channel {
+    Sky,
+    Block(usize),
+}
+
+const CHANNELS: [Channel; 4] = [Channel::Sky, Channel::Block(0), Channel::Block(1), Channel::Block(2)];
+
+fn offset(position: [i32; 3], step: [i32; 3]) -> [i32; 3] {
+    [position[0] + step[0], position[1] + step[1], position[2] + step[2]]
+}
+
+fn get_light(region: &Region, position: [i32; 3], channel: Channel) -> Option<u8> {
+    match channel {
+        Channel::Sky => region.get_sky_light(position),
+        Channel::Block(color) => Some(region.get_block_light(position)?[color]),
+    }
+}
+
+fn set_light(region: &mut Region, position: [i32; 3], channel: Channel, level: u8) {
+    match channel {
+        Channel::Sky => region.set_sky_light(position, level),
+        Channel::Block(color) => region.set_block_light(position, color, level),
+    }
+}
+
+// light that reaches a voxel from a neighbour with the given level, full blocks stop it,
+// leaves and fluids dim it a little more than air and skylight keeps its strength going straight down
+fn spread(level: u8, down: bool, definition: &BlockDefinition) -> u8 {
+    if definition.opacity == Opacity::Full {
+        return 0;
+    }
+    let extra = (definition.opacity == Opacity::Cutout || definition.fluid) as u8;
+    let base = if down && level == MAX_LIGHT {
+        MAX_LIGHT
+    } else {
+        level.saturating_sub(1)
+    };
+    base.saturating_sub(extra)
+}
+
+// each level is a fifth dimmer than the one above it
+pub fn brightness(level: u8) -> f32 {
+    MIN_BRIGHTNESS + (1.0 - MIN_BRIGHTNESS) * 0.8f32.powi((MAX_LIGHT - level.min(MAX_LIGHT)) as i32)
+}
+
+fn propagate(region: &mut Region, channel: Channel, mut queue: VecDeque<[i32; 3]>) {
+    while let Some(position) = queue.pop_front() {
+        let Some(level) = get_light(region, position, channel) else {
+            continue;
+        };
+        for step in NEIGHBOURS {
+            let neighbour = offset(position, step);
+            let (Some(block_type), Some(neighbour_level)) =
+                (region.get_block(neighbour), get_light(region, neighbour, channel))
+            else {
+                continue;
+            };
+            let lit = spread(level, channel == Channel::Sky && step[1] < 0, block_type.definition());
+            if lit > neighbour_level {
+                set_light(region, neighbour, channel, lit);
+                queue.push_back(neighbour);
+            }
+        }
+    }
+}
+
+// light a voxel has of its own: the open sky above the world lights the top layer
+// and emissive blocks give off their color
+fn reseed(region: &mut Region, position: [i32; 3], channel: Channel, queue: &mut VecDeque<[i32; 3]>) {
+    let Some(block_type) = region.get_block(position) else {
+        return;
+    };
+    let level = match channel {
+        Channel::Sky if position[1] == CHUNK_SIZE as i32 - 1 => spread(MAX_LIGHT, true, block_type.definition()),
+        Channel::Sky => 0,
+        Channel::Block(color) => block_type.definition().emission[color],
+    };
+    if level > 0 {
+        set_light(region, position, channel, level);
+        queue.push_back(position);
+    }
+}
+
+// lights the whole region from scratch, skylight straight down every column first and then sideways,
+// block light floods out from every emissive block
+pub fn light_region(region: &mut Region) {
+    let mut emitters: Vec<[i32; 3]> = Vec::new();
+    let size = CHUNK_SIZE as i32;
+    for chunk in region.chunk_buffer.iter_mut() {
+        chunk.block_light.fill([0; 3]);
+        let origin = [chunk.world_position[0] as i32 * size, 0, chunk.world_position[2] as i32 * size];
+        chunk.blocks.for_each_active(&mut |x, y, z, block_type| {
+            if block_type.definition().light_level > 0 {
+                emitters.push([origin[0] + x as i32, y as i32, origin[2] + z as i32]);
+            }
+        });
+        // open sky is the common case, starting from it leaves only the shaded sections to expand
+        chunk.sky_light.fill(MAX_LIGHT);
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let mut level = MAX_LIGHT;
+                for y in (0..CHUNK_SIZE).rev() {
+                    level = spread(level, true, chunk.get_voxel(x, y, z).block_type.definition());
+                    chunk.sky_light.set(x, y, z, level);
+                }
+            }
+        }
+        chunk.sky_light.compact();
+        chunk.dirty = true;
+    }
+    // the flood fill into caves and under overhangs starts wherever a lit voxel borders a darker one
+    let mut queue: VecDeque<[i32; 3]> = VecDeque::new();
+    for chunk in region.chunk_buffer.iter() {
+        let origin = [chunk.world_position[0] as i32 * size, 0, chunk.world_position[2] as i32 * size];
+        for x in 0..size {
+            for z in 0..size {
+                for y in 0..size {
+                    let level = chunk.sky_light.get(x as usize, y as usize, z as usize);
+                    if level <= 1 {
+                        continue;
+                    }
+                    let borders_darker = HORIZONTAL.iter().any(|step| {
+                        let (nx, nz) = (x + step[0], z + step[2]);
+                        // most neighbours are already bright enough, which needs no block lookup
+                        let (block_type, neighbour_level) = if (0..size).contains(&nx) && (0..size).contains(&nz) {
+                            let neighbour_level = chunk.sky_light.get(nx as usize, y as usize, nz as usize);
+                            if neighbour_level + 1 >= level {
+                                return false;
+                            }
+                            (chunk.get_voxel(nx as usize, y as usize, nz as usize).block_type, neighbour_level)
+                        } else {
+                            let neighbour = [origin[0] + nx, y, origin[2] + nz];
+                            match (region.get_block(neighbour), region.get_sky_light(neighbour)) {
+                                (Some(block_type), Some(neighbour_level)) => (block_type, neighbour_level),
+                                _ => return false,
+                            }
+                        };
+                        spread(level, false, block_type.definition()) > neighbour_level
+                    });
+                    if borders_darker {
+                        queue.push_back([origin[0] + x, y, origin[2] + z]);
+                    }
+                }
+            }
+        }
+    }
+    propagate(region, Channel::Sky, queue);
+    for channel in CHANNELS.into_iter().skip(1) {
+        let mut queue: VecDeque<[i32; 3]> = VecDeque::new();
+        for position in emitters.iter() {
+            reseed(region, *position, channel, &mut queue);
+        }
+        propagate(region, channel, queue);
+    }
+    region.light_updates.clear();
+}
+
+// updates the light around every block changed since the last call: everything that got its light
+// through a changed voxel goes dark first, then the brighter voxels around that area fill it in again,
+// so removing a torch only darkens what the torch lit
+pub fn relight(region: &mut Region) {
+    let updates: Vec<[i32; 3]> = std::mem::take(&mut region.light_updates).into_iter().collect();
+    if updates.is_empty() {
+        return;
+    }
+    for channel in CHANNELS {
+        let mut removal: VecDeque<([i32; 3], u8)> = VecDeque::new();
+        let mut queue: VecDeque<[i32; 3]> = VecDeque::new();
+        for position in updates.iter() {
+            let Some(level) = get_light(region, *position, channel) else {
+                continue;
+            };
+            set_light(region, *position, channel, 0);
+            removal.push_back((*position, level));
+            reseed(region, *position, channel, &mut queue);
+        }
+        while let Some((position, level)) = removal.pop_front() {
+            for step in NEIGHBOURS {
+                let neighbour = offset(position, step);
+                let Some(neighbour_level) = get_light(region, neighbour, channel) else {
+                    continue;
+                };
+                if neighbour_level == 0 {
+                    continue;
+                }
+                if neighbour_level < level || (channel == Channel::Sky && step[1] < 0 && level == MAX_LIGHT) {
+                    set_light(region, neighbour, channel, 0);
+                    removal.push_back((neighbour, neighbour_level));
+                    reseed(region, neighbour, channel, &mut queue);
+                } else {
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+        propagate(region, channel, queue);
+    }
+    // darkening and refilling expands sections on the way, most settle back on a single level
+    for chunk in region.chunk_buffer.iter_mut().filter(|chunk| chunk.dirty) {
+        chunk.sky_light.compact();
+        chunk.block_light.compact();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxel::BlockType;
+
+    #[test]
+    fn open_sky_holds_no_light_arrays() {
+        let mut region = Region::empty([0, 0]);
+        light_region(&mut region);
+        for chunk in region.chunk_buffer.iter() {
+            assert!(chunk.sky_light.memory_usage() < 4096);
+        }
+        assert_eq!(region.get_sky_light([5, 0, 5]), Some(MAX_LIGHT));
+
+        // a slab across the chunk corner only shades the sections below it
+        region.fill_box([-8, 40, -8], [7, 40, 7], BlockType::STONE);
+        relight(&mut region);
+        assert_eq!(region.get_sky_light([0, 30, 0]), Some(MAX_LIGHT - 8));
+        assert_eq!(region.get_sky_light([0, 50, 0]), Some(MAX_LIGHT));
+        let chunk = &region.chunk_buffer[region.chunk_index(0, 0).unwrap()];
+        assert!(chunk.sky_light.memory_usage() < 64 * 64 * 64 / 4);
+    }
+
+    #[test]
+    fn block_light_only_expands_around_emitters() {
+        let glowstone = BlockType::from_name("glowstone").unwrap();
+        let mut region = Region::empty([0, 0]);
+        light_region(&mut region);
+        for chunk in region.chunk_buffer.iter() {
+            assert!(chunk.block_light.memory_usage() < 4096);
+        }
+
+        region.set_block([20, 20, 20], glowstone);
+        relight(&mut region);
+        let lit = region.get_block_light([22, 20, 20]).unwrap();
+        assert!(lit.iter().any(|level| *level > 0));
+        let chunk = &region.chunk_buffer[region.chunk_index(0, 0).unwrap()];
+        assert!(chunk.block_light.memory_usage() < 64 * 64 * 64);
+
+        // with the emitter gone everything goes dark and the sections collapse again
+        region.set_block([20, 20, 20], BlockType::AIR);
+        relight(&mut region);
+        assert_eq!(region.get_block_light([22, 20, 20]), Some([0; 3]));
+        for chunk in region.chunk_buffer.iter() {
+            assert!(chunk.block_light.memory_usage() < 4096);
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use crate::chunk::CHUNK_SIZE;
+
+const SECTION_SIZE: usize = 16;
+const SECTIONS_PER_AXIS: usize = CHUNK_SIZE / SECTION_SIZE;
+const SECTION_CUBED: usize = SECTION_SIZE * SECTION_SIZE * SECTION_SIZE;
+
+// a 16^3 part of the chunk, only given its own array once two of its voxels differ
+#[derive(Clone)]
+enum LightSection<T> {
+    Uniform(T),
+    Dense(Box<[T]>),
+}
+
+// light levels of a chunk split into sections, so open sky and solid rock cost one value per section
+#[derive(Clone)]
+pub struct LightStorage<T> {
+    sections: Vec<LightSection<T>>,
+}
+
+impl<T: Copy + PartialEq> LightStorage<T> {
+    pub fn new_uniform(level: T) -> Self {
+        Self {
+            sections: vec![LightSection::Uniform(level); SECTIONS_PER_AXIS * SECTIONS_PER_AXIS * SECTIONS_PER_AXIS],
+        }
+    }
+
+    // the section holding the voxel and the voxel's index inside that section
+    fn locate(x: usize, y: usize, z: usize) -> (usize, usize) {
+        let section = ((x / SECTION_SIZE) * SECTIONS_PER_AXIS + y / SECTION_SIZE) * SECTIONS_PER_AXIS + z / SECTION_SIZE;
+        let index = ((x % SECTION_SIZE) * SECTION_SIZE + y % SECTION_SIZE) * SECTION_SIZE + z % SECTION_SIZE;
+        (section, index)
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> T {
+        let (section, index) = Self::locate(x, y, z);
+        match &self.sections[section] {
+            LightSection::Uniform(level) => *level,
+            LightSection::Dense(levels) => levels[index],
+        }
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize, level: T) {
+        let (section, index) = Self::locate(x, y, z);
+        match &mut self.sections[section] {
+            LightSection::Uniform(current) if *current == level => {}
+            LightSection::Uniform(current) => {
+                let mut levels = vec![*current; SECTION_CUBED].into_boxed_slice();
+                levels[index] = level;
+                self.sections[section] = LightSection::Dense(levels);
+            }
+            LightSection::Dense(levels) => levels[index] = level,
+        }
+    }
+
+    pub fn fill(&mut self, level: T) {
+        for section in self.sections.iter_mut() {
+            *section = LightSection::Uniform(level);
+        }
+    }
+
+    // drops the arrays of sections that ended up holding a single level again
+    pub fn compact(&mut self) {
+        for section in self.sections.iter_mut() {
+            if let LightSection::Dense(levels) = section {
+                let first = levels[0];
+                if levels.iter().all(|level| *level == first) {
+                    *section = LightSection::Uniform(first);
+                }
+            }
+        }
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.sections.capacity() * std::mem::size_of::<LightSection<T>>()
+            + self
+                .sections
+                .iter()
+                .filter(|section| matches!(section, LightSection::Dense(_)))
+                .count()
+                * SECTION_CUBED
+                * std::mem::size_of::<T>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dense_sections<T>(storage: &LightStorage<T>) -> usize {
+        storage
+            .sections
+            .iter()
+            .filter(|section| matches!(section, LightSection::Dense(_)))
+            .count()
+    }
+
+    #[test]
+    fn uniform_light_holds_no_arrays() {
+        let mut storage = LightStorage::new_uniform(15u8);
+        storage.set(3, 40, 63, 15);
+        assert_eq!(dense_sections(&storage), 0);
+        assert_eq!(storage.get(63, 63, 63), 15);
+        assert!(storage.memory_usage() < SECTION_CUBED);
+    }
+
+    #[test]
+    fn writes_only_expand_their_own_section() {
+        let mut storage = LightStorage::new_uniform([0u8; 3]);
+        storage.set(17, 2, 33, [4, 5, 6]);
+        storage.set(18, 2, 33, [1, 0, 0]);
+        assert_eq!(dense_sections(&storage), 1);
+        assert_eq!(storage.get(17, 2, 33), [4, 5, 6]);
+        assert_eq!(storage.get(18, 2, 33), [1, 0, 0]);
+        assert_eq!(storage.get(16, 2, 33), [0; 3]);
+        assert_eq!(storage.get(1, 2, 33), [0; 3]);
+
+        storage.set(40, 63, 0, [9, 9, 9]);
+        assert_eq!(dense_sections(&storage), 2);
+        assert_eq!(storage.get(40, 63, 0), [9, 9, 9]);
+    }
+
+    #[test]
+    fn compact_collapses_sections_that_became_uniform() {
+        let mut storage = LightStorage::new_uniform(0u8);
+        storage.set(5, 5, 5, 7);
+        storage.set(50, 5, 5, 7);
+        storage.set(5, 5, 5, 0);
+        storage.compact();
+        assert_eq!(dense_sections(&storage), 1);
+        assert_eq!(storage.get(50, 5, 5), 7);
+
+        storage.fill(15);
+        assert_eq!(dense_sections(&storage), 0);
+        assert_eq!(storage.get(50, 5, 5), 15);
+    }
+}
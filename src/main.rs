@@ -1,19 +1,47 @@
+mod block_registry;
+mod brush;
 mod camera;
+mod cave;
 mod chunk;
+mod clipboard;
+mod continent;
+mod edit_delta;
+mod falling;
+mod fluid;
 mod generation_params;
 mod gui;
+mod history;
+mod light;
+mod light_storage;
+mod mesh_export;
+mod nbt;
+mod octree;
+mod operations;
+mod palette;
 mod quad;
+mod raycast;
 mod region;
+mod schematic;
 mod state;
+mod storage;
+mod structure;
 mod texture;
 mod vertex;
+mod vox;
 mod voxel;
-use gui::GuiRenderer;
-use state::State;
+mod world_save;
+use block_registry::BlockRegistry;
+use clipboard::Clipboard;
+use gui::{GuiRenderer, Tool};
+use state::{Shortcut, State};
+use storage::StorageKind;
+use voxel::BlockType;
+use winit::event::{ElementState, MouseButton, WindowEvent};
 use std::sync::Arc;
 
 const WINDOW_WIDTH: u32 = 1360;
 const WINDOW_HEIGHT: u32 = 768;
+const PICK_DISTANCE: f32 = 256.0;
 
 async fn run() {
     let event_loop = winit::event_loop::EventLoop::new().unwrap();
@@ -26,11 +54,24 @@ async fn run() {
     let mut state = State::new(window.clone()).await.unwrap();
     let mut last_render_time = instant::Instant::now();
     let mut egui_renderer = GuiRenderer::new(&state.device, state.surface_format, None, 1, &window);
+    let storage_kind = if std::env::args().any(|arg| arg == "--octree") {
+        StorageKind::Octree
+    } else {
+        StorageKind::Palette
+    };
     let generation_params = egui_renderer.get_generation_params();
-    let region = region::Region::new([0, 0], generation_params);
+    let mut region = region::Region::new([0, 0], generation_params);
+    region.set_storage_kind(storage_kind);
+    if std::env::args().any(|arg| arg == "--storage-benchmark") {
+        storage::benchmark(&region);
+    }
     let (vertices, indices) = region.build_mesh();
     state.set_buffers(vertices, indices);
     state.render(&mut egui_renderer, &window);
+    let mut clipboard: Option<Clipboard> = None;
+    let mut stroke: Option<brush::Stroke> = None;
+    let mut last_fluid_tick = instant::Instant::now();
+    let mut last_falling_tick = instant::Instant::now();
     event_loop.set_control_flow(winit::event_loop::ControlFlow::Poll);
 
     let _ = event_loop.run(move |event, window_target| match event {
@@ -38,8 +79,14 @@ async fn run() {
             ref event,
             window_id: _,
         } => {
-            egui_renderer.handle_input(&window, event);
-            if !state.handle_input(event) {
+            let consumed_by_gui = egui_renderer.handle_input(&window, event);
+            // releases always reach the state so a stroke ending over a window does not keep going
+            if consumed_by_gui
+                && matches!(event, WindowEvent::MouseInput { state: ElementState::Released, .. })
+            {
+                state.handle_input(event);
+            }
+            if consumed_by_gui || !state.handle_input(event) {
                 match event {
                     winit::event::WindowEvent::CloseRequested => {
                         window_target.exit();
@@ -51,11 +98,259 @@ async fn run() {
                         state.update(dt);
                         if egui_renderer.updated {
                             let generation_params = egui_renderer.get_generation_params();
-                            let region = region::Region::new([0, 0], generation_params);
+                            region = region::Region::new([0, 0], generation_params);
+                            region.set_storage_kind(storage_kind);
                             let (vertices, indices) = region.build_mesh();
                             state.set_buffers(vertices, indices);
                         }
                         egui_renderer.updated = false;
+                        let (origin, direction) = state.cursor_ray(window.inner_size());
+                        let hit = raycast::raycast(&region, origin, direction, PICK_DISTANCE, &raycast::collidable);
+                        state.set_highlight(hit.as_ref().map(|hit| hit.position), egui_renderer.selection.corners());
+                        egui_renderer.pointed_at = hit.as_ref().map_or(String::new(), |hit| {
+                            format!("{} ({:?} face, {:.1} away)", hit.block_type.definition().name, hit.side, hit.distance)
+                        });
+                        region.history.set_depth(egui_renderer.history_depth);
+                        let mut edited = false;
+                        let mut shortcuts = std::mem::take(&mut state.shortcuts);
+                        if egui_renderer.undo_requested {
+                            shortcuts.push(Shortcut::Undo);
+                            egui_renderer.undo_requested = false;
+                        }
+                        if egui_renderer.redo_requested {
+                            shortcuts.push(Shortcut::Redo);
+                            egui_renderer.redo_requested = false;
+                        }
+                        for shortcut in shortcuts {
+                            edited |= match shortcut {
+                                Shortcut::Undo => region.undo(),
+                                Shortcut::Redo => region.redo(),
+                            };
+                        }
+                        for button in std::mem::take(&mut state.clicks) {
+                            let Some(hit) = &hit else {
+                                continue;
+                            };
+                            edited |= match (egui_renderer.tool, button) {
+                                (Tool::Build, MouseButton::Left) => region.set_block(hit.position, BlockType::AIR),
+                                (Tool::Build, MouseButton::Right) => {
+                                    region.set_block(hit.previous, egui_renderer.selected_block)
+                                }
+                                (Tool::Select, MouseButton::Left) => {
+                                    egui_renderer.selection.first = Some(hit.position);
+                                    false
+                                }
+                                (Tool::Select, MouseButton::Right) => {
+                                    egui_renderer.selection.second = Some(hit.position);
+                                    false
+                                }
+                                _ => false,
+                            };
+                        }
+                        // the whole stroke, from press to release, is one undo step
+                        if egui_renderer.tool == Tool::Brush && state.held_buttons.contains(&MouseButton::Left) {
+                            if let Some(hit) = &hit {
+                                let stroke = stroke.get_or_insert_with(|| {
+                                    region.history.begin_group();
+                                    brush::Stroke::new(hit.position[1])
+                                });
+                                if stroke.step_due() {
+                                    let settings = egui_renderer.get_brush_settings();
+                                    edited |= brush::apply(&mut region, &settings, hit.position, stroke.target_height) > 0;
+                                }
+                            }
+                        } else if stroke.take().is_some() {
+                            region.history.end_group();
+                        }
+                        if egui_renderer.copy_requested {
+                            egui_renderer.status = match egui_renderer.selection.corners() {
+                                Some((min, max)) => {
+                                    let copied = Clipboard::copy(&region, min, max);
+                                    let status = format!(
+                                        "Copied {}x{}x{} blocks",
+                                        copied.size[0], copied.size[1], copied.size[2]
+                                    );
+                                    clipboard = Some(copied);
+                                    status
+                                }
+                                None => String::from("Pick both corners before copying"),
+                            };
+                            egui_renderer.copy_requested = false;
+                        }
+                        if egui_renderer.rotate_requested {
+                            clipboard = clipboard.as_ref().map(|clipboard| clipboard.rotate());
+                            egui_renderer.rotate_requested = false;
+                        }
+                        if let Some(axis) = egui_renderer.mirror_requested.take() {
+                            clipboard = clipboard.as_ref().map(|clipboard| clipboard.mirror(axis));
+                        }
+                        if let Some(operation) = egui_renderer.operation_requested.take() {
+                            egui_renderer.status = match egui_renderer.selection.corners() {
+                                Some((min, max)) => {
+                                    let changed = operations::apply(
+                                        &mut region,
+                                        operation,
+                                        min,
+                                        max,
+                                        egui_renderer.selected_block,
+                                        egui_renderer.replaced_block,
+                                    );
+                                    edited |= changed > 0;
+                                    format!("{} changed {} blocks", operation.name(), changed)
+                                }
+                                None => String::from("Pick both corners first"),
+                            };
+                        }
+                        if egui_renderer.paste_requested {
+                            match (&clipboard, &hit) {
+                                (Some(clipboard), Some(hit)) => {
+                                    let changed = clipboard.paste(&mut region, hit.previous, egui_renderer.skip_air);
+                                    egui_renderer.status = format!("Pasted {} blocks", changed);
+                                    edited = true;
+                                }
+                                (None, _) => egui_renderer.status = String::from("Clipboard is empty"),
+                                (_, None) => egui_renderer.status = String::from("Point at a block to paste"),
+                            }
+                            egui_renderer.paste_requested = false;
+                        }
+                        if now - last_falling_tick >= falling::TICK_INTERVAL {
+                            last_falling_tick = now;
+                            edited |= falling::tick(&mut region);
+                        }
+                        if now - last_fluid_tick >= fluid::TICK_INTERVAL {
+                            last_fluid_tick = now;
+                            edited |= fluid::tick(&mut region);
+                        }
+                        // only the chunks touched by the edit are meshed again
+                        if edited {
+                            let (vertices, indices) = region.build_mesh();
+                            state.set_buffers(vertices, indices);
+                        }
+                        if egui_renderer.clipboard_save_requested {
+                            egui_renderer.status = match &clipboard {
+                                Some(clipboard) => match clipboard
+                                    .to_bytes()
+                                    .and_then(|bytes| std::fs::write(&egui_renderer.clipboard_path, bytes))
+                                {
+                                    Ok(()) => format!("Saved clipboard to {}", egui_renderer.clipboard_path),
+                                    Err(error) => format!("Save failed: {}", error),
+                                },
+                                None => String::from("Clipboard is empty"),
+                            };
+                            egui_renderer.clipboard_save_requested = false;
+                        }
+                        if egui_renderer.clipboard_load_requested {
+                            egui_renderer.status = match std::fs::read(&egui_renderer.clipboard_path)
+                                .and_then(|bytes| Clipboard::from_bytes(&bytes))
+                            {
+                                Ok(loaded) => {
+                                    clipboard = Some(loaded);
+                                    format!("Loaded clipboard from {}", egui_renderer.clipboard_path)
+                                }
+                                Err(error) => format!("Load failed: {}", error),
+                            };
+                            egui_renderer.clipboard_load_requested = false;
+                        }
+                        if egui_renderer.save_requested {
+                            let path = std::path::Path::new(&egui_renderer.world_path);
+                            egui_renderer.status = match world_save::save_world(path, &region) {
+                                Ok(()) => format!(
+                                    "Saved {} edits to {}",
+                                    region.edits.len(),
+                                    egui_renderer.world_path
+                                ),
+                                Err(error) => format!("Save failed: {}", error),
+                            };
+                            egui_renderer.save_requested = false;
+                        }
+                        if egui_renderer.load_requested {
+                            let path = std::path::Path::new(&egui_renderer.world_path);
+                            egui_renderer.status = match world_save::load_world(path, storage_kind) {
+                                Ok(loaded) => {
+                                    region = loaded;
+                                    egui_renderer.set_generation_params(&region.generation_params);
+                                    let (vertices, indices) = region.build_mesh();
+                                    state.set_buffers(vertices, indices);
+                                    format!("Loaded {}", egui_renderer.world_path)
+                                }
+                                Err(error) => format!("Load failed: {}", error),
+                            };
+                            egui_renderer.load_requested = false;
+                        }
+                        if egui_renderer.import_requested {
+                            // models land on the targeted cell, or at ground level in the middle
+                            let origin = hit.as_ref().map_or(
+                                [0, region.generation_params.ground_level as i32, 0],
+                                |hit| hit.previous,
+                            );
+                            egui_renderer.status = match std::fs::read(&egui_renderer.model_path)
+                                .and_then(|bytes| vox::parse(&bytes))
+                            {
+                                Ok(model) => {
+                                    let changed = vox::import(&mut region, &model, origin);
+                                    let (vertices, indices) = region.build_mesh();
+                                    state.set_buffers(vertices, indices);
+                                    format!("Imported {} blocks from {}", changed, egui_renderer.model_path)
+                                }
+                                Err(error) => format!("Import failed: {}", error),
+                            };
+                            egui_renderer.import_requested = false;
+                        }
+                        if egui_renderer.export_requested {
+                            let (min, max) = region.bounds();
+                            egui_renderer.status = match vox::export(&region, min, max)
+                                .and_then(|bytes| std::fs::write(&egui_renderer.model_path, bytes))
+                            {
+                                Ok(()) => format!("Exported region to {}", egui_renderer.model_path),
+                                Err(error) => format!("Export failed: {}", error),
+                            };
+                            egui_renderer.export_requested = false;
+                        }
+                        if egui_renderer.schematic_import_requested {
+                            let origin = hit.as_ref().map_or(
+                                [0, region.generation_params.ground_level as i32, 0],
+                                |hit| hit.previous,
+                            );
+                            egui_renderer.status = match std::fs::read(&egui_renderer.schematic_path)
+                                .and_then(|bytes| schematic::parse(&bytes))
+                            {
+                                Ok(schematic) => {
+                                    let changed = schematic::import(&mut region, &schematic, origin);
+                                    let (vertices, indices) = region.build_mesh();
+                                    state.set_buffers(vertices, indices);
+                                    if schematic.unknown.is_empty() {
+                                        format!("Imported {} blocks from {}", changed, egui_renderer.schematic_path)
+                                    } else {
+                                        format!(
+                                            "Imported {} blocks, read as stone: {}",
+                                            changed,
+                                            schematic.unknown.join(", ")
+                                        )
+                                    }
+                                }
+                                Err(error) => format!("Import failed: {}", error),
+                            };
+                            egui_renderer.schematic_import_requested = false;
+                        }
+                        if egui_renderer.schematic_export_requested {
+                            let (min, max) = region.bounds();
+                            egui_renderer.status =
+                                match schematic::export(&region, min, max, egui_renderer.schematic_version)
+                                    .and_then(|bytes| std::fs::write(&egui_renderer.schematic_path, bytes))
+                                {
+                                    Ok(()) => format!("Exported region to {}", egui_renderer.schematic_path),
+                                    Err(error) => format!("Export failed: {}", error),
+                                };
+                            egui_renderer.schematic_export_requested = false;
+                        }
+                        if egui_renderer.mesh_export_requested {
+                            let path = std::path::Path::new(&egui_renderer.mesh_path);
+                            egui_renderer.status = match mesh_export::export_mesh(&mut region, path) {
+                                Ok(()) => format!("Exported mesh to {}", egui_renderer.mesh_path),
+                                Err(error) => format!("Mesh export failed: {}", error),
+                            };
+                            egui_renderer.mesh_export_requested = false;
+                        }
                         state.render(&mut egui_renderer, &window);
                         window.request_redraw();
                     }
@@ -68,8 +363,45 @@ async fn run() {
     });
 }
 
+// writes the mesh of the default region, or of a saved world given with --world, without a window
+fn export_mesh_headless(args: &[String], path: &str) {
+    let world = args
+        .iter()
+        .position(|arg| arg == "--world")
+        .and_then(|index| args.get(index + 1));
+    let mut region = match world {
+        Some(world) => match world_save::load_world(std::path::Path::new(world), StorageKind::Palette) {
+            Ok(region) => region,
+            Err(error) => {
+                eprintln!("Load failed: {}", error);
+                std::process::exit(1);
+            }
+        },
+        None => region::Region::new([0, 0], generation_params::GenerationParams::default()),
+    };
+    match mesh_export::export_mesh(&mut region, std::path::Path::new(path)) {
+        Ok(()) => println!("Exported mesh to {}", path),
+        Err(error) => {
+            eprintln!("Mesh export failed: {}", error);
+            std::process::exit(1);
+        }
+    }
+}
+
 fn main() {
     println!("Hello, world!");
     env_logger::init();
+    BlockRegistry::init(std::path::Path::new("blocks.toml"));
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(index) = args.iter().position(|arg| arg == "--export-mesh") {
+        match args.get(index + 1) {
+            Some(path) => export_mesh_headless(&args, path),
+            None => {
+                eprintln!("--export-mesh needs an output path");
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
     pollster::block_on(run());
 }
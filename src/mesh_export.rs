@@ -0,0 +1,386 @@
+use crate::region::Region;
+use crate::vertex::Vertex;
+use crate::world_save::{invalid_data, ByteWriter};
+
+use serde_json::json;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const GLB_MAGIC: u32 = 0x4654_6C67;
+const GLB_JSON_CHUNK: u32 = 0x4E4F_534A;
+const GLB_BIN_CHUNK: u32 = 0x004E_4942;
+const GLTF_FLOAT: u32 = 5126;
+const GLTF_UNSIGNED_INT: u32 = 5125;
+const GLTF_ARRAY_BUFFER: u32 = 34962;
+const GLTF_ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+pub struct MeshPart {
+    pub name: &'static str,
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    pub translucent: bool,
+}
+
+// terrain and water as separate meshes, glass and ice share the water mesh so they keep blending
+pub fn mesh_parts(region: &mut Region) -> Vec<MeshPart> {
+    let ((vertices, indices), (water_vertices, water_indices)) = region.build_mesh_parts();
+    vec![
+        MeshPart {
+            name: "terrain",
+            vertices,
+            indices,
+            translucent: false,
+        },
+        MeshPart {
+            name: "water",
+            vertices: water_vertices,
+            indices: water_indices,
+            translucent: true,
+        },
+    ]
+}
+
+// picks the format from the extension: .obj (plus .mtl), .ply (water in a second file) or .glb
+pub fn export_mesh(region: &mut Region, path: &Path) -> io::Result<()> {
+    let parts = mesh_parts(region);
+    // glTF buffers and accessors can't be empty, and an empty OBJ or PLY is no more use
+    if parts.iter().all(|part| part.indices.is_empty()) {
+        return Err(invalid_data("nothing to export, the region has no geometry"));
+    }
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("obj") => write_obj(path, &parts),
+        Some("ply") => write_ply(path, &parts),
+        Some("glb") => fs::write(path, glb(&parts)),
+        _ => Err(invalid_data("unknown mesh format, use .obj, .ply or .glb")),
+    }
+}
+
+fn average_alpha(part: &MeshPart) -> f32 {
+    if part.vertices.is_empty() {
+        return 1.0;
+    }
+    part.vertices.iter().map(|vertex| vertex.color[3]).sum::<f32>() / part.vertices.len() as f32
+}
+
+fn sibling_path(path: &Path, suffix: &str, extension: &str) -> PathBuf {
+    let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("mesh");
+    path.with_file_name(format!("{}{}.{}", stem, suffix, extension))
+}
+
+// vertex colors go after the position, which Blender and MeshLab both read
+fn write_obj(path: &Path, parts: &[MeshPart]) -> io::Result<()> {
+    let material_path = sibling_path(path, "", "mtl");
+    let mut materials = BufWriter::new(fs::File::create(&material_path)?);
+    for part in parts.iter() {
+        writeln!(materials, "newmtl {}", part.name)?;
+        writeln!(materials, "Kd 1 1 1")?;
+        writeln!(materials, "d {}", average_alpha(part))?;
+    }
+    materials.flush()?;
+
+    let mut writer = BufWriter::new(fs::File::create(path)?);
+    writeln!(
+        writer,
+        "mtllib {}",
+        material_path.file_name().and_then(|name| name.to_str()).unwrap_or_default()
+    )?;
+    let mut index_start = 1;
+    for part in parts.iter().filter(|part| !part.vertices.is_empty()) {
+        writeln!(writer, "o {}", part.name)?;
+        writeln!(writer, "usemtl {}", part.name)?;
+        for vertex in part.vertices.iter() {
+            let [x, y, z, _] = vertex.position;
+            let [r, g, b, _] = vertex.color;
+            writeln!(writer, "v {} {} {} {} {} {}", x, y, z, r, g, b)?;
+        }
+        for triangle in part.indices.chunks(3) {
+            writeln!(
+                writer,
+                "f {} {} {}",
+                triangle[0] + index_start,
+                triangle[1] + index_start,
+                triangle[2] + index_start
+            )?;
+        }
+        index_start += part.vertices.len() as u32;
+    }
+    writer.flush()
+}
+
+// PLY holds a single mesh, so every part after the first goes to <name>_<part>.ply
+fn write_ply(path: &Path, parts: &[MeshPart]) -> io::Result<()> {
+    for (index, part) in parts.iter().enumerate() {
+        let part_path = if index == 0 {
+            path.to_path_buf()
+        } else {
+            sibling_path(path, &format!("_{}", part.name), "ply")
+        };
+        let mut writer = BufWriter::new(fs::File::create(part_path)?);
+        write!(
+            writer,
+            "ply\nformat binary_little_endian 1.0\ncomment {}\nelement vertex {}\n\
+             property float x\nproperty float y\nproperty float z\n\
+             property uchar red\nproperty uchar green\nproperty uchar blue\nproperty uchar alpha\n\
+             element face {}\nproperty list uchar uint vertex_indices\nend_header\n",
+            part.name,
+            part.vertices.len(),
+            part.indices.len() / 3
+        )?;
+        for vertex in part.vertices.iter() {
+            for coordinate in &vertex.position[..3] {
+                writer.write_all(&coordinate.to_le_bytes())?;
+            }
+            for channel in vertex.color {
+                writer.write_all(&[(channel.clamp(0.0, 1.0) * 255.0).round() as u8])?;
+            }
+        }
+        for triangle in part.indices.chunks(3) {
+            writer.write_all(&[3])?;
+            for index in triangle {
+                writer.write_all(&index.to_le_bytes())?;
+            }
+        }
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+fn glb(parts: &[MeshPart]) -> Vec<u8> {
+    let mut buffer = ByteWriter::default();
+    let mut buffer_views = Vec::new();
+    let mut accessors = Vec::new();
+    let mut meshes = Vec::new();
+    let mut materials = Vec::new();
+    let mut nodes = Vec::new();
+    // glTF needs at least one element per accessor, so empty parts are left out
+    for part in parts.iter().filter(|part| !part.vertices.is_empty()) {
+        let mut min = [f32::MAX; 3];
+        let mut max = [f32::MIN; 3];
+        let start = buffer.bytes.len();
+        for vertex in part.vertices.iter() {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(vertex.position[axis]);
+                max[axis] = max[axis].max(vertex.position[axis]);
+                buffer.bytes.extend_from_slice(&vertex.position[axis].to_le_bytes());
+            }
+        }
+        buffer_views.push(json!({
+            "buffer": 0,
+            "byteOffset": start,
+            "byteLength": buffer.bytes.len() - start,
+            "target": GLTF_ARRAY_BUFFER,
+        }));
+        accessors.push(json!({
+            "bufferView": buffer_views.len() - 1,
+            "componentType": GLTF_FLOAT,
+            "count": part.vertices.len(),
+            "type": "VEC3",
+            "min": min,
+            "max": max,
+        }));
+        let start = buffer.bytes.len();
+        for vertex in part.vertices.iter() {
+            for channel in vertex.color {
+                buffer.bytes.extend_from_slice(&channel.to_le_bytes());
+            }
+        }
+        buffer_views.push(json!({
+            "buffer": 0,
+            "byteOffset": start,
+            "byteLength": buffer.bytes.len() - start,
+            "target": GLTF_ARRAY_BUFFER,
+        }));
+        accessors.push(json!({
+            "bufferView": buffer_views.len() - 1,
+            "componentType": GLTF_FLOAT,
+            "count": part.vertices.len(),
+            "type": "VEC4",
+        }));
+        let start = buffer.bytes.len();
+        for index in part.indices.iter() {
+            buffer.u32(*index);
+        }
+        buffer_views.push(json!({
+            "buffer": 0,
+            "byteOffset": start,
+            "byteLength": buffer.bytes.len() - start,
+            "target": GLTF_ELEMENT_ARRAY_BUFFER,
+        }));
+        accessors.push(json!({
+            "bufferView": buffer_views.len() - 1,
+            "componentType": GLTF_UNSIGNED_INT,
+            "count": part.indices.len(),
+            "type": "SCALAR",
+        }));
+        materials.push(json!({
+            "name": part.name,
+            "pbrMetallicRoughness": {
+                "baseColorFactor": [1.0, 1.0, 1.0, 1.0],
+                "metallicFactor": 0.0,
+                "roughnessFactor": 1.0,
+            },
+            "alphaMode": if part.translucent { "BLEND" } else { "OPAQUE" },
+        }));
+        meshes.push(json!({
+            "name": part.name,
+            "primitives": [{
+                "attributes": {
+                    "POSITION": accessors.len() - 3,
+                    "COLOR_0": accessors.len() - 2,
+                },
+                "indices": accessors.len() - 1,
+                "material": materials.len() - 1,
+            }],
+        }));
+        nodes.push(json!({ "name": part.name, "mesh": meshes.len() - 1 }));
+    }
+    let document = json!({
+        "asset": { "version": "2.0", "generator": "voxels" },
+        "scene": 0,
+        "scenes": [{ "nodes": (0..nodes.len()).collect::<Vec<usize>>() }],
+        "nodes": nodes,
+        "meshes": meshes,
+        "materials": materials,
+        "accessors": accessors,
+        "bufferViews": buffer_views,
+        "buffers": [{ "byteLength": buffer.bytes.len() }],
+    });
+    let mut json_bytes = document.to_string().into_bytes();
+    // both chunks have to be 4 byte aligned, JSON is padded with spaces
+    while json_bytes.len() % 4 != 0 {
+        json_bytes.push(b' ');
+    }
+    while buffer.bytes.len() % 4 != 0 {
+        buffer.bytes.push(0);
+    }
+    let mut writer = ByteWriter::default();
+    writer.u32(GLB_MAGIC);
+    writer.u32(2);
+    writer.u32((12 + 8 + json_bytes.len() + 8 + buffer.bytes.len()) as u32);
+    writer.u32(json_bytes.len() as u32);
+    writer.u32(GLB_JSON_CHUNK);
+    writer.bytes.extend_from_slice(&json_bytes);
+    writer.u32(buffer.bytes.len() as u32);
+    writer.u32(GLB_BIN_CHUNK);
+    writer.bytes.extend_from_slice(&buffer.bytes);
+    writer.bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxel::BlockType;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("voxels-{}-{}", std::process::id(), name))
+    }
+
+    fn region_with_geometry() -> Region {
+        let mut region = Region::empty([0, 0]);
+        region.fill_box([0, 10, 0], [2, 11, 1], BlockType::STONE);
+        region.set_block([10, 10, 10], BlockType::WATER);
+        region
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn empty_regions_are_not_exported() {
+        let path = temp_path("empty.glb");
+        assert!(export_mesh(&mut Region::empty([0, 0]), &path).is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn obj_holds_every_vertex_and_face() {
+        let mut region = region_with_geometry();
+        let parts = mesh_parts(&mut region);
+        let path = temp_path("round-trip.obj");
+        export_mesh(&mut region, &path).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        let material = fs::read_to_string(sibling_path(&path, "", "mtl")).unwrap();
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(sibling_path(&path, "", "mtl"));
+
+        let vertex_count: usize = parts.iter().map(|part| part.vertices.len()).sum();
+        let face_count: usize = parts.iter().map(|part| part.indices.len() / 3).sum();
+        assert!(parts.iter().all(|part| !part.indices.is_empty()));
+        assert_eq!(text.lines().filter(|line| line.starts_with("v ")).count(), vertex_count);
+        assert_eq!(text.lines().filter(|line| line.starts_with("f ")).count(), face_count);
+        assert_eq!(text.lines().filter(|line| line.starts_with("o ")).count(), 2);
+        // indices are 1 based and run across the parts
+        let highest = text
+            .lines()
+            .filter_map(|line| line.strip_prefix("f "))
+            .flat_map(|line| line.split(' ').map(|index| index.parse::<usize>().unwrap()))
+            .max();
+        assert_eq!(highest, Some(vertex_count));
+        assert!(material.contains("newmtl terrain") && material.contains("newmtl water"));
+    }
+
+    #[test]
+    fn ply_headers_match_their_bodies() {
+        let mut region = region_with_geometry();
+        let parts = mesh_parts(&mut region);
+        let path = temp_path("round-trip.ply");
+        export_mesh(&mut region, &path).unwrap();
+        let files = [path.clone(), sibling_path(&path, "_water", "ply")];
+        for (part, file) in parts.iter().zip(files.iter()) {
+            let bytes = fs::read(file).unwrap();
+            let _ = fs::remove_file(file);
+            let header_end = bytes.windows(11).position(|window| window == b"end_header\n").unwrap() + 11;
+            let header = std::str::from_utf8(&bytes[..header_end]).unwrap();
+            let count = |element: &str| -> usize {
+                header
+                    .lines()
+                    .find_map(|line| line.strip_prefix(element))
+                    .unwrap()
+                    .parse()
+                    .unwrap()
+            };
+            assert_eq!(count("element vertex "), part.vertices.len());
+            assert_eq!(count("element face "), part.indices.len() / 3);
+            // 3 floats and 4 colour bytes per vertex, a count byte and 3 indices per face
+            assert_eq!(
+                bytes.len() - header_end,
+                part.vertices.len() * 16 + part.indices.len() / 3 * 13
+            );
+        }
+    }
+
+    #[test]
+    fn glb_chunks_are_aligned_and_sized() {
+        let mut region = region_with_geometry();
+        let parts = mesh_parts(&mut region);
+        let bytes = glb(&parts);
+        assert_eq!(u32_at(&bytes, 0), GLB_MAGIC);
+        assert_eq!(u32_at(&bytes, 4), 2);
+        assert_eq!(u32_at(&bytes, 8) as usize, bytes.len());
+
+        let json_length = u32_at(&bytes, 12) as usize;
+        assert_eq!(u32_at(&bytes, 16), GLB_JSON_CHUNK);
+        assert_eq!(json_length % 4, 0);
+        let bin_start = 20 + json_length;
+        let bin_length = u32_at(&bytes, bin_start) as usize;
+        assert_eq!(u32_at(&bytes, bin_start + 4), GLB_BIN_CHUNK);
+        assert_eq!(bin_length % 4, 0);
+        assert_eq!(bin_start + 8 + bin_length, bytes.len());
+
+        let document: serde_json::Value = serde_json::from_slice(&bytes[20..bin_start]).unwrap();
+        let buffer_length = document["buffers"][0]["byteLength"].as_u64().unwrap() as usize;
+        assert!(buffer_length > 0 && buffer_length <= bin_length && bin_length - buffer_length < 4);
+        let mut end = 0;
+        for view in document["bufferViews"].as_array().unwrap() {
+            let offset = view["byteOffset"].as_u64().unwrap() as usize;
+            let length = view["byteLength"].as_u64().unwrap() as usize;
+            assert_eq!(offset, end);
+            assert!(length > 0);
+            end = offset + length;
+        }
+        assert_eq!(end, buffer_length);
+        assert_eq!(document["meshes"].as_array().unwrap().len(), 2);
+    }
+}
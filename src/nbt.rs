@@ -0,0 +1,281 @@
+use crate::world_save::invalid_data;
+
+use std::io;
+
+const TAG_END: u8 = 0;
+const TAG_BYTE: u8 = 1;
+const TAG_SHORT: u8 = 2;
+const TAG_INT: u8 = 3;
+const TAG_LONG: u8 = 4;
+const TAG_FLOAT: u8 = 5;
+const TAG_DOUBLE: u8 = 6;
+const TAG_BYTE_ARRAY: u8 = 7;
+const TAG_STRING: u8 = 8;
+const TAG_LIST: u8 = 9;
+const TAG_COMPOUND: u8 = 10;
+const TAG_INT_ARRAY: u8 = 11;
+const TAG_LONG_ARRAY: u8 = 12;
+// real files nest a handful of levels, this only guards against hostile input
+const MAX_DEPTH: usize = 512;
+
+// Minecraft's named binary tag format, big endian throughout
+#[derive(Clone, PartialEq, Debug)]
+pub enum Tag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<u8>),
+    String(String),
+    List(Vec<Tag>),
+    // entries keep their file order
+    Compound(Vec<(String, Tag)>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Tag {
+    pub fn get(&self, name: &str) -> Option<&Tag> {
+        match self {
+            Tag::Compound(entries) => entries
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, tag)| tag),
+            _ => None,
+        }
+    }
+
+    // integer tags of any width, schematics are not consistent about them
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Tag::Byte(value) => Some(*value as i64),
+            Tag::Short(value) => Some(*value as i64),
+            Tag::Int(value) => Some(*value as i64),
+            Tag::Long(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn id(&self) -> u8 {
+        match self {
+            Tag::Byte(_) => TAG_BYTE,
+            Tag::Short(_) => TAG_SHORT,
+            Tag::Int(_) => TAG_INT,
+            Tag::Long(_) => TAG_LONG,
+            Tag::Float(_) => TAG_FLOAT,
+            Tag::Double(_) => TAG_DOUBLE,
+            Tag::ByteArray(_) => TAG_BYTE_ARRAY,
+            Tag::String(_) => TAG_STRING,
+            Tag::List(_) => TAG_LIST,
+            Tag::Compound(_) => TAG_COMPOUND,
+            Tag::IntArray(_) => TAG_INT_ARRAY,
+            Tag::LongArray(_) => TAG_LONG_ARRAY,
+        }
+    }
+}
+
+struct NbtReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> NbtReader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| invalid_data("unexpected end of NBT data"))?;
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn i16(&mut self) -> io::Result<i16> {
+        Ok(i16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> io::Result<i64> {
+        Ok(i64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn len(&mut self) -> io::Result<usize> {
+        let len = self.i32()?;
+        usize::try_from(len).map_err(|_| invalid_data("negative NBT length"))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.i16()? as u16 as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn payload(&mut self, id: u8, depth: usize) -> io::Result<Tag> {
+        if depth > MAX_DEPTH {
+            return Err(invalid_data("NBT nested too deeply"));
+        }
+        Ok(match id {
+            TAG_BYTE => Tag::Byte(self.u8()? as i8),
+            TAG_SHORT => Tag::Short(self.i16()?),
+            TAG_INT => Tag::Int(self.i32()?),
+            TAG_LONG => Tag::Long(self.i64()?),
+            TAG_FLOAT => Tag::Float(f32::from_bits(self.i32()? as u32)),
+            TAG_DOUBLE => Tag::Double(f64::from_bits(self.i64()? as u64)),
+            TAG_BYTE_ARRAY => {
+                let len = self.len()?;
+                Tag::ByteArray(self.take(len)?.to_vec())
+            }
+            TAG_STRING => Tag::String(self.string()?),
+            TAG_LIST => {
+                let element_id = self.u8()?;
+                let len = self.len()?;
+                let mut elements = Vec::new();
+                for _ in 0..len {
+                    elements.push(self.payload(element_id, depth + 1)?);
+                }
+                Tag::List(elements)
+            }
+            TAG_COMPOUND => {
+                let mut entries = Vec::new();
+                loop {
+                    let entry_id = self.u8()?;
+                    if entry_id == TAG_END {
+                        break;
+                    }
+                    let name = self.string()?;
+                    entries.push((name, self.payload(entry_id, depth + 1)?));
+                }
+                Tag::Compound(entries)
+            }
+            TAG_INT_ARRAY => {
+                let len = self.len()?;
+                Tag::IntArray((0..len).map(|_| self.i32()).collect::<io::Result<Vec<i32>>>()?)
+            }
+            TAG_LONG_ARRAY => {
+                let len = self.len()?;
+                Tag::LongArray((0..len).map(|_| self.i64()).collect::<io::Result<Vec<i64>>>()?)
+            }
+            _ => return Err(invalid_data("unknown NBT tag")),
+        })
+    }
+}
+
+// reads the root tag, returning its name and value
+pub fn read(bytes: &[u8]) -> io::Result<(String, Tag)> {
+    let mut reader = NbtReader { bytes, position: 0 };
+    let id = reader.u8()?;
+    if id != TAG_COMPOUND {
+        return Err(invalid_data("NBT root is not a compound"));
+    }
+    let name = reader.string()?;
+    Ok((name, reader.payload(id, 0)?))
+}
+
+fn write_string(bytes: &mut Vec<u8>, value: &str) {
+    bytes.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(value.as_bytes());
+}
+
+fn write_payload(bytes: &mut Vec<u8>, tag: &Tag) {
+    match tag {
+        Tag::Byte(value) => bytes.push(*value as u8),
+        Tag::Short(value) => bytes.extend_from_slice(&value.to_be_bytes()),
+        Tag::Int(value) => bytes.extend_from_slice(&value.to_be_bytes()),
+        Tag::Long(value) => bytes.extend_from_slice(&value.to_be_bytes()),
+        Tag::Float(value) => bytes.extend_from_slice(&value.to_be_bytes()),
+        Tag::Double(value) => bytes.extend_from_slice(&value.to_be_bytes()),
+        Tag::ByteArray(values) => {
+            bytes.extend_from_slice(&(values.len() as i32).to_be_bytes());
+            bytes.extend_from_slice(values);
+        }
+        Tag::String(value) => write_string(bytes, value),
+        Tag::List(elements) => {
+            bytes.push(elements.first().map_or(TAG_END, |element| element.id()));
+            bytes.extend_from_slice(&(elements.len() as i32).to_be_bytes());
+            for element in elements.iter() {
+                write_payload(bytes, element);
+            }
+        }
+        Tag::Compound(entries) => {
+            for (name, entry) in entries.iter() {
+                bytes.push(entry.id());
+                write_string(bytes, name);
+                write_payload(bytes, entry);
+            }
+            bytes.push(TAG_END);
+        }
+        Tag::IntArray(values) => {
+            bytes.extend_from_slice(&(values.len() as i32).to_be_bytes());
+            for value in values.iter() {
+                bytes.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+        Tag::LongArray(values) => {
+            bytes.extend_from_slice(&(values.len() as i32).to_be_bytes());
+            for value in values.iter() {
+                bytes.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+    }
+}
+
+pub fn write(name: &str, root: &Tag) -> Vec<u8> {
+    let mut bytes = vec![root.id()];
+    write_string(&mut bytes, name);
+    write_payload(&mut bytes, root);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_tag_round_trips() {
+        let root = Tag::Compound(vec![
+            ("byte".to_string(), Tag::Byte(-3)),
+            ("short".to_string(), Tag::Short(-2)),
+            ("int".to_string(), Tag::Int(70_000)),
+            ("long".to_string(), Tag::Long(-5_000_000_000)),
+            ("float".to_string(), Tag::Float(1.5)),
+            ("double".to_string(), Tag::Double(-0.25)),
+            ("bytes".to_string(), Tag::ByteArray(vec![0, 0x80, 0xff])),
+            ("string".to_string(), Tag::String("minecraft:stone".to_string())),
+            ("list".to_string(), Tag::List(vec![Tag::Int(1), Tag::Int(2)])),
+            ("empty".to_string(), Tag::List(Vec::new())),
+            ("nested".to_string(), Tag::Compound(vec![("inner".to_string(), Tag::Short(7))])),
+            ("ints".to_string(), Tag::IntArray(vec![-1, 0, 1])),
+            ("longs".to_string(), Tag::LongArray(vec![i64::MIN, i64::MAX])),
+        ]);
+        let (name, read_back) = read(&write("Schematic", &root)).unwrap();
+        assert_eq!(name, "Schematic");
+        assert_eq!(read_back, root);
+    }
+
+    #[test]
+    fn truncated_and_hostile_data_is_rejected() {
+        let bytes = write("", &Tag::Compound(vec![("data".to_string(), Tag::ByteArray(vec![1, 2, 3]))]));
+        for len in 0..bytes.len() {
+            assert!(read(&bytes[..len]).is_err());
+        }
+        // a byte array claiming far more than the file holds
+        let mut bytes = vec![TAG_COMPOUND, 0, 0, TAG_BYTE_ARRAY, 0, 1, b'a'];
+        bytes.extend_from_slice(&i32::MAX.to_be_bytes());
+        assert!(read(&bytes).is_err());
+        // compounds nested past the depth limit
+        let mut bytes = vec![TAG_COMPOUND, 0, 0];
+        for _ in 0..MAX_DEPTH + 1 {
+            bytes.extend_from_slice(&[TAG_COMPOUND, 0, 0]);
+        }
+        assert!(read(&bytes).is_err());
+    }
+}
@@ -0,0 +1,401 @@
+use crate::voxel::{BlockType, Voxel};
+
+// uniform subtrees collapse into a single leaf, so big air or stone volumes cost one node
+pub enum OctreeNode {
+    Leaf(BlockType),
+    Branch(Box<[OctreeNode; 8]>),
+}
+
+pub struct SparseOctree {
+    size: usize,
+    root: OctreeNode,
+}
+
+pub struct OctreeHit {
+    pub position: [usize; 3],
+    pub block_type: BlockType,
+    pub distance: f32,
+}
+
+fn child_index(position: [usize; 3], half: usize) -> usize {
+    ((position[0] >= half) as usize) << 2
+        | ((position[1] >= half) as usize) << 1
+        | (position[2] >= half) as usize
+}
+
+fn child_offset(index: usize, half: usize) -> [usize; 3] {
+    [
+        (index >> 2 & 1) * half,
+        (index >> 1 & 1) * half,
+        (index & 1) * half,
+    ]
+}
+
+impl OctreeNode {
+    fn build(
+        blocks_vector: &[Voxel],
+        linearize: &dyn Fn(usize, usize, usize) -> usize,
+        min: [usize; 3],
+        size: usize,
+    ) -> Self {
+        if size == 1 {
+            return OctreeNode::Leaf(blocks_vector[linearize(min[0], min[1], min[2])].block_type);
+        }
+        let half = size / 2;
+        let children: [OctreeNode; 8] = std::array::from_fn(|i| {
+            let offset = child_offset(i, half);
+            Self::build(
+                blocks_vector,
+                linearize,
+                [min[0] + offset[0], min[1] + offset[1], min[2] + offset[2]],
+                half,
+            )
+        });
+        Self::collapse(children)
+    }
+
+    fn collapse(children: [OctreeNode; 8]) -> Self {
+        if let OctreeNode::Leaf(first) = children[0] {
+            let uniform = children
+                .iter()
+                .all(|child| matches!(child, OctreeNode::Leaf(block_type) if *block_type == first));
+            if uniform {
+                return OctreeNode::Leaf(first);
+            }
+        }
+        OctreeNode::Branch(Box::new(children))
+    }
+
+    fn get(&self, position: [usize; 3], size: usize) -> BlockType {
+        match self {
+            OctreeNode::Leaf(block_type) => *block_type,
+            OctreeNode::Branch(children) => {
+                let half = size / 2;
+                let index = child_index(position, half);
+                children[index].get(position.map(|c| c % half), half)
+            }
+        }
+    }
+
+    fn set(&mut self, position: [usize; 3], size: usize, block_type: BlockType) {
+        if let OctreeNode::Leaf(current) = self {
+            if *current == block_type {
+                return;
+            }
+            if size == 1 {
+                *current = block_type;
+                return;
+            }
+            let current = *current;
+            *self = OctreeNode::Branch(Box::new(std::array::from_fn(|_| OctreeNode::Leaf(current))));
+        }
+        if let OctreeNode::Branch(children) = self {
+            let half = size / 2;
+            let index = child_index(position, half);
+            children[index].set(position.map(|c| c % half), half, block_type);
+            // only the child just written can have made the siblings uniform
+            let OctreeNode::Leaf(written) = children[index] else {
+                return;
+            };
+            let uniform = children
+                .iter()
+                .all(|child| matches!(child, OctreeNode::Leaf(block_type) if *block_type == written));
+            if uniform {
+                *self = OctreeNode::Leaf(written);
+            }
+        }
+    }
+
+    fn node_count(&self) -> usize {
+        match self {
+            OctreeNode::Leaf(_) => 1,
+            OctreeNode::Branch(children) => 1 + children.iter().map(|child| child.node_count()).sum::<usize>(),
+        }
+    }
+
+    fn for_each_leaf(&self, min: [usize; 3], size: usize, visit: &mut dyn FnMut([usize; 3], usize, BlockType)) {
+        match self {
+            OctreeNode::Leaf(block_type) => visit(min, size, *block_type),
+            OctreeNode::Branch(children) => {
+                let half = size / 2;
+                for (i, child) in children.iter().enumerate() {
+                    let offset = child_offset(i, half);
+                    child.for_each_leaf([min[0] + offset[0], min[1] + offset[1], min[2] + offset[2]], half, visit);
+                }
+            }
+        }
+    }
+
+    fn raycast(
+        &self,
+        min: [usize; 3],
+        size: usize,
+        origin: [f32; 3],
+        direction: [f32; 3],
+        max_distance: f32,
+    ) -> Option<OctreeHit> {
+        let (t_enter, _) = ray_box(origin, direction, min, size, max_distance)?;
+        match self {
+            OctreeNode::Leaf(BlockType::AIR) => None,
+            OctreeNode::Leaf(block_type) => {
+                let position = std::array::from_fn(|axis| {
+                    let entry = origin[axis] + direction[axis] * t_enter;
+                    (entry.floor().max(min[axis] as f32) as usize).min(min[axis] + size - 1)
+                });
+                Some(OctreeHit {
+                    position,
+                    block_type: *block_type,
+                    distance: t_enter,
+                })
+            }
+            OctreeNode::Branch(children) => {
+                let half = size / 2;
+                let mut order: Vec<(f32, usize, [usize; 3])> = (0..8)
+                    .filter_map(|i| {
+                        let offset = child_offset(i, half);
+                        let child_min = [min[0] + offset[0], min[1] + offset[1], min[2] + offset[2]];
+                        ray_box(origin, direction, child_min, half, max_distance)
+                            .map(|(t, _)| (t, i, child_min))
+                    })
+                    .collect();
+                order.sort_by(|a, b| a.0.total_cmp(&b.0));
+                order.into_iter().find_map(|(_, i, child_min)| {
+                    children[i].raycast(child_min, half, origin, direction, max_distance)
+                })
+            }
+        }
+    }
+}
+
+// slab test against the box [min, min + size), returns the entry and exit distances
+fn ray_box(
+    origin: [f32; 3],
+    direction: [f32; 3],
+    min: [usize; 3],
+    size: usize,
+    max_distance: f32,
+) -> Option<(f32, f32)> {
+    let mut t_enter: f32 = 0.0;
+    let mut t_exit = max_distance;
+    for axis in 0..3 {
+        let low = min[axis] as f32;
+        let high = (min[axis] + size) as f32;
+        if direction[axis].abs() < f32::EPSILON {
+            if origin[axis] < low || origin[axis] >= high {
+                return None;
+            }
+            continue;
+        }
+        let t0 = (low - origin[axis]) / direction[axis];
+        let t1 = (high - origin[axis]) / direction[axis];
+        t_enter = t_enter.max(t0.min(t1));
+        t_exit = t_exit.min(t0.max(t1));
+    }
+    if t_enter > t_exit {
+        return None;
+    }
+    Some((t_enter, t_exit))
+}
+
+impl SparseOctree {
+    pub fn from_dense(
+        blocks_vector: &[Voxel],
+        size: usize,
+        linearize: &dyn Fn(usize, usize, usize) -> usize,
+    ) -> Self {
+        Self {
+            size,
+            root: OctreeNode::build(blocks_vector, linearize, [0, 0, 0], size),
+        }
+    }
+
+    pub fn to_dense(&self, linearize: &dyn Fn(usize, usize, usize) -> usize) -> Vec<Voxel> {
+        let mut blocks_vector = vec![Voxel::new(BlockType::AIR); self.size * self.size * self.size];
+        self.for_each_leaf(&mut |min, size, block_type| {
+            for x in min[0]..min[0] + size {
+                for y in min[1]..min[1] + size {
+                    for z in min[2]..min[2] + size {
+                        blocks_vector[linearize(x, y, z)] = Voxel::new(block_type);
+                    }
+                }
+            }
+        });
+        blocks_vector
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> BlockType {
+        self.root.get([x, y, z], self.size)
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize, block_type: BlockType) {
+        self.root.set([x, y, z], self.size, block_type);
+    }
+
+    // visits every uniform box of the tree as (min corner, edge length, block)
+    pub fn for_each_leaf(&self, visit: &mut dyn FnMut([usize; 3], usize, BlockType)) {
+        self.root.for_each_leaf([0, 0, 0], self.size, visit);
+    }
+
+    // origin is in local voxel space where voxel (x, y, z) spans [x, x + 1)
+    pub fn raycast(&self, origin: [f32; 3], direction: [f32; 3], max_distance: f32) -> Option<OctreeHit> {
+        self.root.raycast([0, 0, 0], self.size, origin, direction, max_distance)
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.root.node_count() * std::mem::size_of::<OctreeNode>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::{Chunk, CHUNK_SIZE};
+    use crate::raycast::{collidable, raycast};
+    use crate::region::Region;
+
+    const SIZE: usize = 16;
+
+    fn linearize(x: usize, y: usize, z: usize) -> usize {
+        x * SIZE * SIZE + y * SIZE + z
+    }
+
+    // a solid floor with scattered blocks above it, mixing big uniform boxes with single voxels
+    fn sample_blocks() -> Vec<Voxel> {
+        let mut blocks_vector = vec![Voxel::new(BlockType::AIR); SIZE * SIZE * SIZE];
+        for x in 0..SIZE {
+            for y in 0..SIZE {
+                for z in 0..SIZE {
+                    let block_type = if y < 4 {
+                        BlockType::STONE
+                    } else if (x * 7 + y * 13 + z * 5) % 23 == 0 {
+                        BlockType::DIRT
+                    } else if x == 9 && z < 6 {
+                        BlockType::GRANITE
+                    } else {
+                        BlockType::AIR
+                    };
+                    blocks_vector[linearize(x, y, z)] = Voxel::new(block_type);
+                }
+            }
+        }
+        blocks_vector
+    }
+
+    fn block_types(blocks_vector: &[Voxel]) -> Vec<BlockType> {
+        blocks_vector.iter().map(|voxel| voxel.block_type).collect()
+    }
+
+    #[test]
+    fn dense_round_trips() {
+        let blocks_vector = sample_blocks();
+        let octree = SparseOctree::from_dense(&blocks_vector, SIZE, &linearize);
+        assert_eq!(block_types(&octree.to_dense(&linearize)), block_types(&blocks_vector));
+        for x in 0..SIZE {
+            for y in 0..SIZE {
+                for z in 0..SIZE {
+                    assert_eq!(octree.get(x, y, z), blocks_vector[linearize(x, y, z)].block_type);
+                }
+            }
+        }
+        // the stone floor is made of a few big leaves instead of one per voxel
+        assert!(octree.root.node_count() < SIZE * SIZE * SIZE);
+    }
+
+    #[test]
+    fn set_splits_and_collapses_uniform_subtrees() {
+        let mut octree = SparseOctree::from_dense(&vec![Voxel::new(BlockType::AIR); SIZE * SIZE * SIZE], SIZE, &linearize);
+        assert_eq!(octree.root.node_count(), 1);
+
+        // one branch with eight children on each of the four levels down to a single voxel
+        octree.set(5, 6, 7, BlockType::STONE);
+        assert_eq!(octree.root.node_count(), 1 + 4 * 8);
+        assert_eq!(octree.get(5, 6, 7), BlockType::STONE);
+        assert_eq!(octree.get(5, 6, 6), BlockType::AIR);
+
+        // filling the rest of its 2x2x2 cell collapses that cell back into one leaf
+        for x in 4..6 {
+            for y in 6..8 {
+                for z in 6..8 {
+                    octree.set(x, y, z, BlockType::STONE);
+                }
+            }
+        }
+        assert_eq!(octree.root.node_count(), 1 + 3 * 8);
+        assert_eq!(octree.get(4, 7, 6), BlockType::STONE);
+
+        for x in 4..6 {
+            for y in 6..8 {
+                for z in 6..8 {
+                    octree.set(x, y, z, BlockType::AIR);
+                }
+            }
+        }
+        assert_eq!(octree.root.node_count(), 1);
+        assert_eq!(octree.get(5, 6, 7), BlockType::AIR);
+    }
+
+    #[test]
+    fn set_reuses_the_children_of_mixed_branches() {
+        let blocks_vector = sample_blocks();
+        let mut octree = SparseOctree::from_dense(&blocks_vector, SIZE, &linearize);
+        let children = |octree: &SparseOctree| match &octree.root {
+            OctreeNode::Branch(children) => children.as_ref() as *const [OctreeNode; 8],
+            OctreeNode::Leaf(_) => panic!("root collapsed"),
+        };
+        let before = children(&octree);
+        octree.set(12, 10, 3, BlockType::SLATE);
+        octree.set(0, 0, 0, BlockType::AIR);
+        assert_eq!(children(&octree), before);
+        assert_eq!(octree.get(12, 10, 3), BlockType::SLATE);
+        assert_eq!(octree.get(0, 0, 0), BlockType::AIR);
+    }
+
+    #[test]
+    fn raycast_agrees_with_the_region_raycast() {
+        let mut region = Region::empty([0, 0]);
+        region.fill_box([0, 0, 0], [63, 9, 63], BlockType::STONE);
+        region.fill_box([20, 10, 20], [23, 40, 23], BlockType::GRANITE);
+        region.fill_box([40, 25, 8], [47, 27, 15], BlockType::DIRT);
+        region.set_block([33, 44, 29], BlockType::SLATE);
+        let chunk_index = region.chunk_index(0, 0).unwrap();
+        let octree = SparseOctree::from_dense(
+            &region.chunk_buffer[chunk_index].blocks.to_dense(),
+            CHUNK_SIZE,
+            &Chunk::linearize,
+        );
+
+        let origin = [32.0, 50.0, 32.0];
+        let mut directions: Vec<[f32; 3]> = vec![[0.0, -1.0, 0.0], [1.0, 0.0, 0.0]];
+        for i in 0..64 {
+            let angle = i as f32 / 64.0 * std::f32::consts::TAU + 0.01;
+            directions.push([angle.cos(), -1.3 - (i % 5) as f32 * 0.4, angle.sin()]);
+        }
+        directions.push([0.1, -0.1, -0.9]);
+        directions.push([0.2, 1.0, 0.05]);
+        let mut hits = 0;
+        for direction in directions {
+            let length = direction.iter().map(|c| c * c).sum::<f32>().sqrt();
+            let direction = direction.map(|c| c / length);
+            let expected = raycast(&region, origin, direction, 100.0, &collidable);
+            // the octree works in local voxel space where voxel x spans [x, x + 1)
+            let hit = octree.raycast(origin.map(|c| c + 0.5), direction, 100.0);
+            match (expected, hit) {
+                (Some(expected), Some(hit)) => {
+                    assert_eq!(expected.position, hit.position.map(|c| c as i32), "direction {:?}", direction);
+                    assert_eq!(expected.block_type, hit.block_type);
+                    assert!((expected.distance - hit.distance).abs() < 1e-3);
+                    hits += 1;
+                }
+                (None, None) => {}
+                (expected, hit) => panic!(
+                    "direction {:?}: region hit {:?}, octree hit {:?}",
+                    direction,
+                    expected.map(|expected| expected.position),
+                    hit.map(|hit| hit.position)
+                ),
+            }
+        }
+        assert!(hits >= 64, "{} hits", hits);
+    }
+}
@@ -0,0 +1,256 @@
+use crate::chunk::CHUNK_SIZE;
+use crate::region::Region;
+use crate::voxel::BlockType;
+
+// bulk edits over the selection, each one is a single undo step and a single remesh
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Operation {
+    Fill,
+    Replace,
+    Hollow,
+    Walls,
+    Outline,
+    Sphere,
+    Cylinder,
+}
+
+impl Operation {
+    pub const ALL: [Operation; 7] = [
+        Operation::Fill,
+        Operation::Replace,
+        Operation::Hollow,
+        Operation::Walls,
+        Operation::Outline,
+        Operation::Sphere,
+        Operation::Cylinder,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Operation::Fill => "Fill",
+            Operation::Replace => "Replace",
+            Operation::Hollow => "Hollow",
+            Operation::Walls => "Walls",
+            Operation::Outline => "Outline",
+            Operation::Sphere => "Sphere",
+            Operation::Cylinder => "Cylinder",
+        }
+    }
+}
+
+// squared distance from the centre of the box, scaled so the inscribed shape ends at 1
+fn normalized_distance(position: [i32; 3], min: [i32; 3], max: [i32; 3], axes: &[usize]) -> f32 {
+    axes.iter()
+        .map(|axis| {
+            let centre = (min[*axis] + max[*axis]) as f32 / 2.0;
+            let radius = (max[*axis] - min[*axis]) as f32 / 2.0 + 0.5;
+            let offset = (position[*axis] as f32 - centre) / radius;
+            offset * offset
+        })
+        .sum()
+}
+
+// solid blocks whose six neighbours are all solid, checked before anything is removed; everything
+// outside the selection counts as open so the outer layer of the selection stays as a shell
+fn interior(region: &Region, min: [i32; 3], max: [i32; 3]) -> Vec<[i32; 3]> {
+    let solid = |position: [i32; 3]| {
+        (0..3).all(|axis| (min[axis]..=max[axis]).contains(&position[axis]))
+            && region.get_block(position).is_some_and(|block_type| block_type != BlockType::AIR)
+    };
+    let mut positions = Vec::new();
+    for x in min[0]..=max[0] {
+        for y in min[1]..=max[1] {
+            for z in min[2]..=max[2] {
+                let enclosed = solid([x, y, z])
+                    && [[1, 0, 0], [0, 1, 0], [0, 0, 1]].iter().all(|step| {
+                        solid([x + step[0], y + step[1], z + step[2]])
+                            && solid([x - step[0], y - step[1], z - step[2]])
+                    });
+                if enclosed {
+                    positions.push([x, y, z]);
+                }
+            }
+        }
+    }
+    positions
+}
+
+// min and max are the inclusive selection corners, block_type is what gets placed
+// and replaced is the block a replace looks for, returns how many blocks changed
+pub fn apply(
+    region: &mut Region,
+    operation: Operation,
+    min: [i32; 3],
+    max: [i32; 3],
+    block_type: BlockType,
+    replaced: BlockType,
+) -> usize {
+    if operation == Operation::Fill {
+        return region.fill_box(min, max, block_type);
+    }
+    // a cube selection holds a true sphere rather than an ellipsoid
+    if operation == Operation::Sphere && max[0] - min[0] == max[1] - min[1] && max[0] - min[0] == max[2] - min[2] {
+        let centre: [f32; 3] = std::array::from_fn(|axis| (min[axis] + max[axis]) as f32 / 2.0);
+        return region.fill_sphere(centre, (max[0] - min[0]) as f32 / 2.0 + 0.5, block_type);
+    }
+    let targets: Vec<([i32; 3], BlockType)> = if operation == Operation::Hollow {
+        interior(region, min, max)
+            .into_iter()
+            .map(|position| (position, BlockType::AIR))
+            .collect()
+    } else {
+        let mut targets = Vec::new();
+        for x in min[0]..=max[0] {
+            for y in min[1].max(0)..=max[1].min(CHUNK_SIZE as i32 - 1) {
+                for z in min[2]..=max[2] {
+                    let position = [x, y, z];
+                    let on_side = x == min[0] || x == max[0] || z == min[2] || z == max[2];
+                    let included = match operation {
+                        Operation::Replace => region.get_block(position) == Some(replaced),
+                        Operation::Walls => on_side,
+                        Operation::Outline => on_side || y == min[1] || y == max[1],
+                        Operation::Sphere => normalized_distance(position, min, max, &[0, 1, 2]) <= 1.0,
+                        Operation::Cylinder => normalized_distance(position, min, max, &[0, 2]) <= 1.0,
+                        Operation::Fill | Operation::Hollow => true,
+                    };
+                    if included {
+                        targets.push((position, block_type));
+                    }
+                }
+            }
+        }
+        targets
+    };
+    let mut changed = 0;
+    region.history.begin_group();
+    for (position, block_type) in targets {
+        if region.set_block(position, block_type) {
+            changed += 1;
+        }
+    }
+    region.history.end_group();
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks_in(region: &Region, min: [i32; 3], max: [i32; 3], block_type: BlockType) -> usize {
+        let mut count = 0;
+        for x in min[0]..=max[0] {
+            for y in min[1]..=max[1] {
+                for z in min[2]..=max[2] {
+                    if region.get_block([x, y, z]) == Some(block_type) {
+                        count += 1;
+                    }
+                }
+            }
+        }
+        count
+    }
+
+    fn apply_to_empty(operation: Operation, min: [i32; 3], max: [i32; 3]) -> Region {
+        let mut region = Region::empty([0, 0]);
+        apply(&mut region, operation, min, max, BlockType::STONE, BlockType::AIR);
+        region
+    }
+
+    #[test]
+    fn replace_only_touches_the_replaced_block() {
+        let mut region = Region::empty([0, 0]);
+        region.fill_box([0, 10, 0], [3, 10, 3], BlockType::DIRT);
+        region.set_block([1, 10, 1], BlockType::GRANITE);
+        let changed = apply(&mut region, Operation::Replace, [0, 10, 0], [3, 11, 3], BlockType::STONE, BlockType::DIRT);
+        assert_eq!(changed, 15);
+        assert_eq!(region.get_block([1, 10, 1]), Some(BlockType::GRANITE));
+        assert_eq!(blocks_in(&region, [0, 11, 0], [3, 11, 3], BlockType::AIR), 16);
+        assert!(region.undo());
+        assert_eq!(blocks_in(&region, [0, 10, 0], [3, 10, 3], BlockType::DIRT), 15);
+    }
+
+    #[test]
+    fn walls_and_outline_cover_the_sides_of_the_box() {
+        let (min, max) = ([0, 10, 0], [3, 12, 4]);
+        let region = apply_to_empty(Operation::Walls, min, max);
+        // a 4 by 5 ring on each of the three layers
+        assert_eq!(blocks_in(&region, min, max, BlockType::STONE), 14 * 3);
+        assert_eq!(region.get_block([0, 11, 2]), Some(BlockType::STONE));
+        assert_eq!(region.get_block([3, 12, 4]), Some(BlockType::STONE));
+        assert_eq!(region.get_block([1, 12, 2]), Some(BlockType::AIR));
+        assert_eq!(region.get_block([2, 10, 3]), Some(BlockType::AIR));
+
+        let region = apply_to_empty(Operation::Outline, min, max);
+        // the walls plus the inside of the top and bottom layers
+        assert_eq!(blocks_in(&region, min, max, BlockType::STONE), 14 * 3 + 6 * 2);
+        assert_eq!(region.get_block([1, 12, 2]), Some(BlockType::STONE));
+        assert_eq!(region.get_block([2, 10, 3]), Some(BlockType::STONE));
+        assert_eq!(region.get_block([1, 11, 2]), Some(BlockType::AIR));
+        // nothing outside the selection
+        assert_eq!(blocks_in(&region, [-1, 9, -1], [4, 13, 5], BlockType::STONE), 14 * 3 + 6 * 2);
+    }
+
+    #[test]
+    fn hollow_keeps_the_shell_of_a_free_standing_box() {
+        let (min, max) = ([0, 10, 0], [4, 14, 4]);
+        let mut region = apply_to_empty(Operation::Fill, min, max);
+        let changed = apply(&mut region, Operation::Hollow, min, max, BlockType::STONE, BlockType::AIR);
+        assert_eq!(changed, 27);
+        assert_eq!(blocks_in(&region, [1, 11, 1], [3, 13, 3], BlockType::AIR), 27);
+        assert_eq!(blocks_in(&region, min, max, BlockType::STONE), 125 - 27);
+    }
+
+    #[test]
+    fn hollow_inside_terrain_keeps_the_outer_layer_of_the_selection() {
+        let mut region = Region::empty([0, 0]);
+        region.fill_box([-2, 8, -2], [6, 16, 6], BlockType::STONE);
+        let (min, max) = ([0, 10, 0], [4, 14, 4]);
+        let changed = apply(&mut region, Operation::Hollow, min, max, BlockType::STONE, BlockType::AIR);
+        assert_eq!(changed, 27);
+        assert_eq!(blocks_in(&region, [1, 11, 1], [3, 13, 3], BlockType::AIR), 27);
+        // the selection's own faces stay, as does everything around it
+        assert_eq!(blocks_in(&region, min, max, BlockType::STONE), 125 - 27);
+        assert_eq!(blocks_in(&region, [-2, 8, -2], [6, 16, 6], BlockType::STONE), 9 * 9 * 9 - 27);
+    }
+
+    #[test]
+    fn spheres_fit_odd_and_even_boxes() {
+        // odd cube, centred on a voxel
+        let (min, max) = ([0, 10, 0], [4, 14, 4]);
+        let region = apply_to_empty(Operation::Sphere, min, max);
+        assert_eq!(blocks_in(&region, [-1, 9, -1], [5, 15, 5], BlockType::STONE), 81);
+        assert_eq!(region.get_block([2, 12, 2]), Some(BlockType::STONE));
+        assert_eq!(region.get_block([2, 10, 2]), Some(BlockType::STONE));
+        assert_eq!(region.get_block([0, 10, 0]), Some(BlockType::AIR));
+
+        // even cube, centred between voxels
+        let (min, max) = ([0, 10, 0], [3, 13, 3]);
+        let region = apply_to_empty(Operation::Sphere, min, max);
+        assert_eq!(blocks_in(&region, [-1, 9, -1], [4, 14, 4], BlockType::STONE), 32);
+        assert_eq!(region.get_block([1, 11, 2]), Some(BlockType::STONE));
+        assert_eq!(region.get_block([0, 11, 1]), Some(BlockType::STONE));
+        assert_eq!(region.get_block([0, 10, 1]), Some(BlockType::AIR));
+
+        // boxes that are not cubes get an ellipsoid
+        let region = apply_to_empty(Operation::Sphere, [0, 10, 0], [4, 12, 6]);
+        assert_eq!(blocks_in(&region, [-1, 9, -1], [5, 13, 7], BlockType::STONE), 61);
+        let region = apply_to_empty(Operation::Sphere, [0, 10, 0], [3, 12, 5]);
+        assert_eq!(blocks_in(&region, [-1, 9, -1], [4, 13, 6], BlockType::STONE), 36);
+    }
+
+    #[test]
+    fn cylinders_fit_odd_and_even_boxes() {
+        let (min, max) = ([0, 10, 0], [4, 13, 4]);
+        let region = apply_to_empty(Operation::Cylinder, min, max);
+        // a 21 voxel disc on each layer
+        assert_eq!(blocks_in(&region, [-1, 9, -1], [5, 14, 5], BlockType::STONE), 21 * 4);
+        assert_eq!(region.get_block([0, 13, 2]), Some(BlockType::STONE));
+        assert_eq!(region.get_block([0, 13, 0]), Some(BlockType::AIR));
+
+        let (min, max) = ([0, 10, 0], [3, 12, 5]);
+        let region = apply_to_empty(Operation::Cylinder, min, max);
+        assert_eq!(blocks_in(&region, [-1, 9, -1], [4, 13, 6], BlockType::STONE), 20 * 3);
+        assert_eq!(region.get_block([0, 11, 2]), Some(BlockType::STONE));
+        assert_eq!(region.get_block([0, 11, 0]), Some(BlockType::AIR));
+    }
+}
@@ -0,0 +1,237 @@
+use crate::voxel::{BlockType, Voxel};
+
+// per-chunk palette of block types plus bit-packed indices into it,
+// indices never straddle two words so reads stay a shift and a mask
+pub struct PaletteStorage {
+    len: usize,
+    palette: Vec<BlockType>,
+    bits_per_index: u32,
+    data: Vec<u64>,
+}
+
+impl PaletteStorage {
+    pub fn new_uniform(len: usize, block_type: BlockType) -> Self {
+        Self {
+            len,
+            palette: vec![block_type],
+            bits_per_index: 0,
+            data: Vec::new(),
+        }
+    }
+
+    pub fn from_dense(blocks_vector: &[Voxel]) -> Self {
+        let mut palette: Vec<BlockType> = Vec::new();
+        let mut indices: Vec<usize> = Vec::with_capacity(blocks_vector.len());
+        for voxel in blocks_vector.iter() {
+            let index = match palette.iter().position(|entry| *entry == voxel.block_type) {
+                Some(index) => index,
+                None => {
+                    palette.push(voxel.block_type);
+                    palette.len() - 1
+                }
+            };
+            indices.push(index);
+        }
+        if palette.len() <= 1 {
+            return Self::new_uniform(
+                blocks_vector.len(),
+                palette.first().copied().unwrap_or(BlockType::AIR),
+            );
+        }
+        let mut storage = Self {
+            len: blocks_vector.len(),
+            bits_per_index: Self::bits_for(palette.len()),
+            palette,
+            data: Vec::new(),
+        };
+        storage.data = vec![0; storage.words_for(storage.bits_per_index)];
+        for (i, index) in indices.into_iter().enumerate() {
+            storage.write_index(i, index);
+        }
+        storage
+    }
+
+    pub fn to_dense(&self) -> Vec<Voxel> {
+        (0..self.len).map(|i| Voxel::new(self.get(i))).collect()
+    }
+
+    pub fn is_uniform(&self) -> bool {
+        self.bits_per_index == 0
+    }
+
+    pub fn palette(&self) -> &[BlockType] {
+        &self.palette
+    }
+
+    pub fn get(&self, i: usize) -> BlockType {
+        if self.bits_per_index == 0 {
+            return self.palette[0];
+        }
+        self.palette[self.read_index(i)]
+    }
+
+    pub fn set(&mut self, i: usize, block_type: BlockType) {
+        let index = match self.palette.iter().position(|entry| *entry == block_type) {
+            Some(index) => index,
+            None => {
+                self.palette.push(block_type);
+                let needed_bits = Self::bits_for(self.palette.len());
+                if needed_bits > self.bits_per_index {
+                    self.repack(needed_bits);
+                }
+                self.palette.len() - 1
+            }
+        };
+        if self.bits_per_index > 0 {
+            self.write_index(i, index);
+        }
+    }
+
+    // entries are never removed by set, so this drops the unreferenced ones once that lets the indices shrink
+    pub fn compact(&mut self) {
+        if self.bits_per_index == 0 {
+            return;
+        }
+        let mut used = vec![false; self.palette.len()];
+        for i in 0..self.len {
+            used[self.read_index(i)] = true;
+        }
+        let used_count = used.iter().filter(|used| **used).count();
+        if Self::bits_for(used_count) < self.bits_per_index {
+            *self = Self::from_dense(&self.to_dense());
+        }
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.palette.capacity() * std::mem::size_of::<BlockType>()
+            + self.data.capacity() * std::mem::size_of::<u64>()
+    }
+
+    fn bits_for(palette_len: usize) -> u32 {
+        if palette_len <= 1 {
+            return 0;
+        }
+        (usize::BITS - (palette_len - 1).leading_zeros()).max(1)
+    }
+
+    fn words_for(&self, bits_per_index: u32) -> usize {
+        let per_word = (64 / bits_per_index) as usize;
+        self.len.div_ceil(per_word)
+    }
+
+    fn read_index(&self, i: usize) -> usize {
+        let per_word = (64 / self.bits_per_index) as usize;
+        let shift = (i % per_word) as u32 * self.bits_per_index;
+        let mask = (1u64 << self.bits_per_index) - 1;
+        ((self.data[i / per_word] >> shift) & mask) as usize
+    }
+
+    fn write_index(&mut self, i: usize, index: usize) {
+        let per_word = (64 / self.bits_per_index) as usize;
+        let shift = (i % per_word) as u32 * self.bits_per_index;
+        let mask = (1u64 << self.bits_per_index) - 1;
+        let word = &mut self.data[i / per_word];
+        *word = (*word & !(mask << shift)) | ((index as u64 & mask) << shift);
+    }
+
+    fn repack(&mut self, bits_per_index: u32) {
+        let old_indices: Vec<usize> = if self.bits_per_index == 0 {
+            vec![0; self.len]
+        } else {
+            (0..self.len).map(|i| self.read_index(i)).collect()
+        };
+        self.bits_per_index = bits_per_index;
+        self.data = vec![0; self.words_for(bits_per_index)];
+        for (i, index) in old_indices.into_iter().enumerate() {
+            self.write_index(i, index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // not a multiple of any indices-per-word count so the last word is only partly used
+    const LEN: usize = 100;
+
+    #[test]
+    fn set_widens_indices_and_keeps_earlier_values() {
+        let mut storage = PaletteStorage::new_uniform(LEN, BlockType::AIR);
+        let mut expected = [BlockType::AIR; LEN];
+        let additions = [
+            (BlockType::STONE, 1),
+            (BlockType::DIRT, 2),
+            (BlockType::GRASS, 2),
+            (BlockType::WATER, 3),
+        ];
+        for (step, (block_type, bits)) in additions.into_iter().enumerate() {
+            // spread each block over several words, including the last partial one
+            for i in (step..LEN).step_by(7) {
+                storage.set(i, block_type);
+                expected[i] = block_type;
+            }
+            assert_eq!(storage.bits_per_index, bits);
+            for (i, expected_type) in expected.iter().enumerate() {
+                assert_eq!(storage.get(i), *expected_type, "index {} after adding {:?}", i, block_type);
+            }
+        }
+        // known blocks reuse their palette entry
+        storage.set(LEN - 1, BlockType::STONE);
+        assert_eq!(storage.palette().len(), 5);
+        assert_eq!(storage.get(LEN - 1), BlockType::STONE);
+    }
+
+    #[test]
+    fn dense_round_trips() {
+        let blocks: Vec<Voxel> = (0..LEN)
+            .map(|i| Voxel::new(BlockType::BUILTIN[i % BlockType::BUILTIN.len()].0))
+            .collect();
+        let storage = PaletteStorage::from_dense(&blocks);
+        assert_eq!(storage.bits_per_index, 4);
+        let round_trip: Vec<BlockType> = storage.to_dense().iter().map(|voxel| voxel.block_type).collect();
+        let original: Vec<BlockType> = blocks.iter().map(|voxel| voxel.block_type).collect();
+        assert_eq!(round_trip, original);
+
+        let uniform = PaletteStorage::from_dense(&[Voxel::new(BlockType::STONE); LEN]);
+        assert!(uniform.is_uniform());
+        assert!(uniform.to_dense().iter().all(|voxel| voxel.block_type == BlockType::STONE));
+    }
+
+    #[test]
+    fn compact_shrinks_indices_once_entries_fall_out_of_use() {
+        let kinds = [BlockType::AIR, BlockType::STONE, BlockType::DIRT, BlockType::GRASS];
+        let mut storage = PaletteStorage::new_uniform(LEN, BlockType::AIR);
+        for i in 0..LEN {
+            storage.set(i, kinds[i % kinds.len()]);
+        }
+        assert_eq!(storage.bits_per_index, 2);
+
+        // three of the four entries still need two bits, so nothing is repacked yet
+        for i in (3..LEN).step_by(kinds.len()) {
+            storage.set(i, BlockType::AIR);
+        }
+        storage.compact();
+        assert_eq!(storage.bits_per_index, 2);
+        assert_eq!(storage.palette().len(), 4);
+
+        for i in (2..LEN).step_by(kinds.len()) {
+            storage.set(i, BlockType::STONE);
+        }
+        storage.compact();
+        assert_eq!(storage.bits_per_index, 1);
+        assert_eq!(storage.palette(), &[BlockType::AIR, BlockType::STONE]);
+        for i in 0..LEN {
+            let expected = if i % kinds.len() == 0 || i % kinds.len() == 3 { BlockType::AIR } else { BlockType::STONE };
+            assert_eq!(storage.get(i), expected, "index {}", i);
+        }
+
+        for i in 0..LEN {
+            storage.set(i, BlockType::DIRT);
+        }
+        storage.compact();
+        assert!(storage.is_uniform());
+        assert_eq!(storage.get(LEN - 1), BlockType::DIRT);
+    }
+}
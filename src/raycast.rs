@@ -0,0 +1,176 @@
+use crate::block_registry::BlockDefinition;
+use crate::quad::{Quad, Side};
+use crate::region::Region;
+use crate::voxel::BlockType;
+
+use enum_iterator::all;
+
+pub struct RaycastHit {
+    pub position: [i32; 3],
+    pub block_type: BlockType,
+    // face of the hit voxel the ray entered through
+    pub side: Side,
+    pub distance: f32,
+    // last cell the ray passed before the hit, where a placed block would go
+    pub previous: [i32; 3],
+}
+
+// counts everything that stops the player, so water and air are passed through
+pub fn collidable(definition: &BlockDefinition) -> bool {
+    definition.collidable
+}
+
+fn side_for(axis: usize, direction: i32) -> Side {
+    all::<Side>()
+        .find(|side| Quad::get_axis_and_direction_for_side(side) == (axis, direction))
+        .unwrap()
+}
+
+// one cell a ray entered, with the cell before it, the axis it crossed to get there and the
+// direction it crossed it in
+pub struct TraversalStep {
+    pub cell: [i32; 3],
+    pub previous: [i32; 3],
+    pub axis: usize,
+    pub step: i32,
+    pub distance: f32,
+}
+
+// Amanatides-Woo traversal in a grid where voxel x spans [x, x + 1), the direction has to be normalized;
+// visits every cell after the one holding the origin until visit accepts one or max_distance is passed
+pub fn traverse(
+    origin: [f32; 3],
+    direction: [f32; 3],
+    max_distance: f32,
+    visit: &mut dyn FnMut(&TraversalStep) -> bool,
+) -> Option<TraversalStep> {
+    let mut cell: [i32; 3] = origin.map(|c| c.floor() as i32);
+    let step: [i32; 3] = direction.map(|c| if c > 0.0 { 1 } else if c < 0.0 { -1 } else { 0 });
+    let t_delta: [f32; 3] = direction.map(|c| if c == 0.0 { f32::INFINITY } else { 1.0 / c.abs() });
+    let mut t_max: [f32; 3] = std::array::from_fn(|axis| {
+        if direction[axis] > 0.0 {
+            (cell[axis] as f32 + 1.0 - origin[axis]) / direction[axis]
+        } else if direction[axis] < 0.0 {
+            (origin[axis] - cell[axis] as f32) / -direction[axis]
+        } else {
+            f32::INFINITY
+        }
+    });
+    loop {
+        let axis = (0..3).min_by(|a, b| t_max[*a].total_cmp(&t_max[*b])).unwrap();
+        let distance = t_max[axis];
+        if distance > max_distance {
+            return None;
+        }
+        let previous = cell;
+        cell[axis] += step[axis];
+        t_max[axis] += t_delta[axis];
+        let entered = TraversalStep {
+            cell,
+            previous,
+            axis,
+            step: step[axis],
+            distance,
+        };
+        if visit(&entered) {
+            return Some(entered);
+        }
+    }
+}
+
+// traversal in world coordinates, voxels are centred on integer positions
+// the cell holding the origin is never reported so rays cast from inside a block can leave it
+pub fn raycast(
+    region: &Region,
+    origin: [f32; 3],
+    direction: [f32; 3],
+    max_distance: f32,
+    filter: &dyn Fn(&BlockDefinition) -> bool,
+) -> Option<RaycastHit> {
+    let length = direction.iter().map(|c| c * c).sum::<f32>().sqrt();
+    if length == 0.0 || !max_distance.is_finite() {
+        return None;
+    }
+    let direction = direction.map(|c| c / length);
+    // shifted so voxel x spans [x, x + 1)
+    let origin = origin.map(|c| c + 0.5);
+    let hit = traverse(origin, direction, max_distance, &mut |entered| {
+        region
+            .get_block(entered.cell)
+            .is_some_and(|block_type| filter(block_type.definition()))
+    })?;
+    Some(RaycastHit {
+        position: hit.cell,
+        block_type: region.get_block(hit.cell)?,
+        side: side_for(hit.axis, -hit.step),
+        distance: hit.distance,
+        previous: hit.previous,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region_with(blocks: &[([i32; 3], BlockType)]) -> Region {
+        let mut region = Region::empty([0, 0]);
+        for (position, block_type) in blocks {
+            region.set_block(*position, *block_type);
+        }
+        region
+    }
+
+    #[test]
+    fn axis_aligned_hits_report_the_entered_face() {
+        let region = region_with(&[([5, 10, 0], BlockType::STONE), ([0, 3, 0], BlockType::STONE)]);
+        let hit = raycast(&region, [0.0, 10.0, 0.0], [1.0, 0.0, 0.0], 20.0, &collidable).unwrap();
+        assert_eq!(hit.position, [5, 10, 0]);
+        assert_eq!(hit.previous, [4, 10, 0]);
+        assert_eq!(hit.side, Side::Right);
+        assert_eq!(hit.block_type, BlockType::STONE);
+        assert!((hit.distance - 4.5).abs() < 1e-5);
+
+        let hit = raycast(&region, [0.0, 10.0, 0.0], [0.0, -2.0, 0.0], 20.0, &collidable).unwrap();
+        assert_eq!(hit.position, [0, 3, 0]);
+        assert_eq!(hit.previous, [0, 4, 0]);
+        assert_eq!(hit.side, Side::Top);
+        assert!((hit.distance - 6.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn diagonal_ray_steps_through_every_crossed_cell() {
+        let region = region_with(&[([3, 12, 0], BlockType::STONE), ([4, 12, 0], BlockType::STONE)]);
+        let hit = raycast(&region, [0.0, 10.0, 0.0], [2.0, 1.0, 0.0], 20.0, &collidable).unwrap();
+        assert_eq!(hit.position, [3, 12, 0]);
+        assert_eq!(hit.previous, [3, 11, 0]);
+        assert_eq!(hit.side, Side::Bottom);
+        assert!((hit.distance - 1.5 * 5f32.sqrt()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn blocks_past_max_distance_are_missed() {
+        let region = region_with(&[([10, 10, 0], BlockType::STONE)]);
+        assert!(raycast(&region, [0.0, 10.0, 0.0], [1.0, 0.0, 0.0], 5.0, &collidable).is_none());
+        assert!(raycast(&region, [0.0, 10.0, 0.0], [1.0, 0.0, 0.0], 10.0, &collidable).is_some());
+        assert!(raycast(&region, [0.0, 10.0, 0.0], [0.0, 0.0, 0.0], 10.0, &collidable).is_none());
+    }
+
+    #[test]
+    fn ray_starting_inside_a_block_leaves_it() {
+        let region = region_with(&[([0, 10, 0], BlockType::STONE), ([3, 10, 0], BlockType::STONE)]);
+        let hit = raycast(&region, [0.2, 10.0, 0.0], [1.0, 0.0, 0.0], 20.0, &collidable).unwrap();
+        assert_eq!(hit.position, [3, 10, 0]);
+        assert_eq!(hit.previous, [2, 10, 0]);
+        assert!((hit.distance - 2.3).abs() < 1e-5);
+    }
+
+    #[test]
+    fn filter_decides_what_counts_as_a_hit() {
+        let region = region_with(&[([2, 10, 0], BlockType::WATER), ([4, 10, 0], BlockType::STONE)]);
+        let hit = raycast(&region, [0.0, 10.0, 0.0], [1.0, 0.0, 0.0], 20.0, &collidable).unwrap();
+        assert_eq!(hit.position, [4, 10, 0]);
+        let hit = raycast(&region, [0.0, 10.0, 0.0], [1.0, 0.0, 0.0], 20.0, &|definition| definition.fluid).unwrap();
+        assert_eq!(hit.position, [2, 10, 0]);
+        assert_eq!(hit.block_type, BlockType::WATER);
+    }
+}
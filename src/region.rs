@@ -1,54 +1,408 @@
 use crate::{
-    chunk::Chunk,
-    generation_params::GenerationParams,
+    chunk::{Chunk, CHUNK_SIZE},
+    continent::ContinentCells,
+    edit_delta::EditDelta,
+    generation_params::{GenerationMode, GenerationParams},
+    history::{EditHistory, DEFAULT_HISTORY_DEPTH},
+    light,
+    storage::StorageKind,
+    structure::StructureTemplate,
     vertex::Vertex,
+    voxel::{BlockType, Voxel},
 };
-const CHUNK_PER_ROW: i32 = 3;
+use std::collections::HashSet;
+pub const CHUNK_PER_ROW: i32 = 3;
+
+pub type MeshBuffers = (Vec<Vertex>, Vec<u32>);
+
+// chunk mesh with indices starting at zero, offset when the region mesh is assembled
+#[derive(Default)]
+struct ChunkMesh {
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+    translucent_vertices: Vec<Vertex>,
+    translucent_indices: Vec<u32>,
+}
 
 pub struct Region {
     pub centre: [i32; 2],
     pub chunk_buffer: Vec<Chunk>,
+    pub generation_params: GenerationParams,
+    pub edits: EditDelta,
+    pub history: EditHistory,
+    // positions changed since the last fluid tick, the simulation looks at them and their neighbours
+    pub fluid_updates: HashSet<[i32; 3]>,
+    // the same for falling blocks, which only care about the voxel itself and the one above
+    pub falling_updates: HashSet<[i32; 3]>,
+    // and for light, relit in one go right before the next mesh is built
+    pub light_updates: HashSet<[i32; 3]>,
+    mesh_cache: Vec<ChunkMesh>,
 }
 
 impl Region {
     pub fn new(centre: [i32; 2], generation_params: GenerationParams) -> Region {
         let perm_table = noise::permutationtable::PermutationTable::new(generation_params.seed);
+        let continent_cells = ContinentCells::new(&generation_params, centre);
+        let structure_templates = if generation_params.structures {
+            StructureTemplate::builtin()
+        } else {
+            Vec::new()
+        };
         let mut chunk_buffer: Vec<Chunk> = Vec::new();
         for x in centre[0] - CHUNK_PER_ROW / 2..=centre[0] + CHUNK_PER_ROW / 2 {
             for z in centre[1] - CHUNK_PER_ROW / 2..=centre[1] + CHUNK_PER_ROW / 2 {
-                let chunk = Chunk::new_perlin2d(
-                    [x as f32, 0.0, z as f32],
-                    generation_params.frequency,
-                    generation_params.octaves,
-                    &perm_table,
-                    generation_params.ground_level as f64,
-                    generation_params.noise_multiplier,
-                    generation_params.water_level as usize,
-                    generation_params.dirt_layer_height as i32,
-                );
+                let chunk = match generation_params.mode {
+                    GenerationMode::Terrain => Chunk::new_perlin2d(
+                        [x as f32, 0.0, z as f32],
+                        &perm_table,
+                        &generation_params,
+                        &continent_cells,
+                        &structure_templates,
+                    ),
+                    GenerationMode::FloatingIslands => Chunk::new_floating_islands(
+                        [x as f32, 0.0, z as f32],
+                        &perm_table,
+                        &generation_params,
+                    ),
+                };
                 chunk_buffer.push(chunk);
             }
         }
+        let mut region = Region::from_chunks(centre, chunk_buffer, generation_params);
+        light::light_region(&mut region);
+        region
+    }
+
+    fn from_chunks(centre: [i32; 2], chunk_buffer: Vec<Chunk>, generation_params: GenerationParams) -> Region {
         Region {
             centre,
+            mesh_cache: chunk_buffer.iter().map(|_| ChunkMesh::default()).collect(),
             chunk_buffer,
+            generation_params,
+            edits: EditDelta::default(),
+            history: EditHistory::new(DEFAULT_HISTORY_DEPTH),
+            fluid_updates: HashSet::new(),
+            falling_updates: HashSet::new(),
+            light_updates: HashSet::new(),
+        }
+    }
+
+    // all air and unlit, for tests that place their blocks by hand
+    #[cfg(test)]
+    pub fn empty(centre: [i32; 2]) -> Region {
+        let air = vec![Voxel::new(BlockType::AIR); CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE];
+        let mut chunk_buffer: Vec<Chunk> = Vec::new();
+        for x in centre[0] - CHUNK_PER_ROW / 2..=centre[0] + CHUNK_PER_ROW / 2 {
+            for z in centre[1] - CHUNK_PER_ROW / 2..=centre[1] + CHUNK_PER_ROW / 2 {
+                chunk_buffer.push(Chunk {
+                    world_position: [x as f32, 0.0, z as f32],
+                    blocks: crate::storage::ChunkStorage::from_dense(&air, StorageKind::Palette),
+                    water_level: 0,
+                    fluid_levels: std::collections::HashMap::new(),
+                    sky_light: crate::light_storage::LightStorage::new_uniform(0),
+                    block_light: crate::light_storage::LightStorage::new_uniform([0; 3]),
+                    modified: false,
+                    dirty: true,
+                });
+            }
+        }
+        Region::from_chunks(centre, chunk_buffer, GenerationParams::default())
+    }
+
+    pub fn chunk_index(&self, chunk_x: i32, chunk_z: i32) -> Option<usize> {
+        self.chunk_buffer.iter().position(|chunk| {
+            chunk.world_position[0] as i32 == chunk_x && chunk.world_position[2] as i32 == chunk_z
+        })
+    }
+
+    // every edit after generation goes through here so it lands in the delta layer
+    pub fn set_chunk_voxel(&mut self, chunk_index: usize, x: usize, y: usize, z: usize, voxel: Voxel) {
+        let chunk = &mut self.chunk_buffer[chunk_index];
+        let chunk_position = [chunk.world_position[0] as i32, chunk.world_position[2] as i32];
+        chunk.set_voxel(x, y, z, voxel);
+        self.edits
+            .record(chunk_position, Chunk::linearize(x, y, z), voxel.block_type);
+    }
+
+    // splits a world voxel position into the chunk holding it and the position inside that chunk
+    fn locate(&self, world_position: [i32; 3]) -> Option<(usize, [usize; 3])> {
+        let size = CHUNK_SIZE as i32;
+        if world_position[1] < 0 || world_position[1] >= size {
+            return None;
         }
+        let chunk_index = self.chunk_index(world_position[0].div_euclid(size), world_position[2].div_euclid(size))?;
+        Some((
+            chunk_index,
+            [
+                world_position[0].rem_euclid(size) as usize,
+                world_position[1] as usize,
+                world_position[2].rem_euclid(size) as usize,
+            ],
+        ))
     }
-    pub fn build_mesh(&self) -> (Vec<Vertex>, Vec<u32>) {
+
+    // inclusive world voxel corners of everything the region holds
+    pub fn bounds(&self) -> ([i32; 3], [i32; 3]) {
+        let size = CHUNK_SIZE as i32;
+        let half = CHUNK_PER_ROW / 2;
+        (
+            [(self.centre[0] - half) * size, 0, (self.centre[1] - half) * size],
+            [
+                (self.centre[0] + half + 1) * size - 1,
+                size - 1,
+                (self.centre[1] + half + 1) * size - 1,
+            ],
+        )
+    }
+
+    pub fn get_block(&self, world_position: [i32; 3]) -> Option<BlockType> {
+        let (chunk_index, [x, y, z]) = self.locate(world_position)?;
+        Some(self.chunk_buffer[chunk_index].get_voxel(x, y, z).block_type)
+    }
+
+    // returns whether the block changed, positions outside the loaded chunks are ignored
+    pub fn set_block(&mut self, world_position: [i32; 3], block_type: BlockType) -> bool {
+        let Some(before) = self.write_block(world_position, block_type) else {
+            return false;
+        };
+        self.history.record(world_position, before, block_type);
+        true
+    }
+
+    // like set_block but kept out of the history, returns the replaced block if anything changed
+    fn write_block(&mut self, world_position: [i32; 3], block_type: BlockType) -> Option<BlockType> {
+        let (chunk_index, [x, y, z]) = self.locate(world_position)?;
+        let before = self.chunk_buffer[chunk_index].get_voxel(x, y, z).block_type;
+        if before == block_type {
+            return None;
+        }
+        self.set_chunk_voxel(chunk_index, x, y, z, Voxel::new(block_type));
+        self.mark_dirty(world_position, chunk_index, x, z);
+        self.fluid_updates.insert(world_position);
+        self.falling_updates.insert(world_position);
+        self.light_updates.insert(world_position);
+        Some(before)
+    }
+
+    // written by the simulations, kept out of the history so undo only covers what the user did
+    pub fn set_block_untracked(&mut self, world_position: [i32; 3], block_type: BlockType) -> bool {
+        self.write_block(world_position, block_type).is_some()
+    }
+
+    pub fn get_sky_light(&self, world_position: [i32; 3]) -> Option<u8> {
+        let (chunk_index, [x, y, z]) = self.locate(world_position)?;
+        Some(self.chunk_buffer[chunk_index].sky_light.get(x, y, z))
+    }
+
+    pub fn set_sky_light(&mut self, world_position: [i32; 3], level: u8) {
+        let Some((chunk_index, [x, y, z])) = self.locate(world_position) else {
+            return;
+        };
+        self.chunk_buffer[chunk_index].sky_light.set(x, y, z, level);
+        self.mark_dirty(world_position, chunk_index, x, z);
+    }
+
+    pub fn get_block_light(&self, world_position: [i32; 3]) -> Option<[u8; 3]> {
+        let (chunk_index, [x, y, z]) = self.locate(world_position)?;
+        Some(self.chunk_buffer[chunk_index].block_light.get(x, y, z))
+    }
+
+    // channel is 0, 1 or 2 for red, green and blue
+    pub fn set_block_light(&mut self, world_position: [i32; 3], channel: usize, level: u8) {
+        let Some((chunk_index, [x, y, z])) = self.locate(world_position) else {
+            return;
+        };
+        let light = &mut self.chunk_buffer[chunk_index].block_light;
+        let mut levels = light.get(x, y, z);
+        levels[channel] = level;
+        light.set(x, y, z, levels);
+        self.mark_dirty(world_position, chunk_index, x, z);
+    }
+
+    fn mark_dirty(&mut self, world_position: [i32; 3], chunk_index: usize, x: usize, z: usize) {
+        self.chunk_buffer[chunk_index].dirty = true;
+        // faces of the next chunk looking into this voxel are culled and shaded by it too
+        if x == 0 || x == CHUNK_SIZE - 1 || z == 0 || z == CHUNK_SIZE - 1 {
+            for step in [[1, 0, 0], [-1, 0, 0], [0, 0, 1], [0, 0, -1]] {
+                let neighbour = [world_position[0] + step[0], world_position[1], world_position[2] + step[2]];
+                if let Some((neighbour_index, _)) = self.locate(neighbour) {
+                    self.chunk_buffer[neighbour_index].dirty = true;
+                }
+            }
+        }
+    }
+
+    // fluid voxels without a stored level are sources
+    pub fn get_fluid_level(&self, world_position: [i32; 3]) -> Option<u8> {
+        let (chunk_index, [x, y, z]) = self.locate(world_position)?;
+        Some(self.chunk_buffer[chunk_index].fluid_level(x, y, z))
+    }
+
+    // like set_block_untracked, also storing how far the fluid has flowed
+    pub fn set_fluid(&mut self, world_position: [i32; 3], block_type: BlockType, level: u8) -> bool {
+        let Some((chunk_index, [x, y, z])) = self.locate(world_position) else {
+            return false;
+        };
+        let chunk = &self.chunk_buffer[chunk_index];
+        if chunk.get_voxel(x, y, z).block_type == block_type && chunk.fluid_level(x, y, z) == level {
+            return false;
+        }
+        self.write_block(world_position, block_type);
+        let chunk = &mut self.chunk_buffer[chunk_index];
+        chunk.set_fluid_level(x, y, z, level);
+        let chunk_position = [chunk.world_position[0] as i32, chunk.world_position[2] as i32];
+        self.edits
+            .record_fluid_level(chunk_position, Chunk::linearize(x, y, z), level);
+        self.fluid_updates.insert(world_position);
+        true
+    }
+
+    pub fn undo(&mut self) -> bool {
+        let Some(command) = self.history.pop_undo() else {
+            return false;
+        };
+        for (position, block_type) in command.undo_changes() {
+            self.write_block(position, block_type);
+        }
+        self.history.push_redo(command);
+        true
+    }
+
+    pub fn redo(&mut self) -> bool {
+        let Some(command) = self.history.pop_redo() else {
+            return false;
+        };
+        for (position, block_type) in command.redo_changes() {
+            self.write_block(position, block_type);
+        }
+        self.history.push_undo(command);
+        true
+    }
+
+    // min and max are inclusive corners, returns how many blocks changed
+    pub fn fill_box(&mut self, min: [i32; 3], max: [i32; 3], block_type: BlockType) -> usize {
+        let low: [i32; 3] = std::array::from_fn(|axis| min[axis].min(max[axis]));
+        let high: [i32; 3] = std::array::from_fn(|axis| min[axis].max(max[axis]));
+        let mut changed = 0;
+        self.history.begin_group();
+        for x in low[0]..=high[0] {
+            for y in low[1].max(0)..=high[1].min(CHUNK_SIZE as i32 - 1) {
+                for z in low[2]..=high[2] {
+                    if self.set_block([x, y, z], block_type) {
+                        changed += 1;
+                    }
+                }
+            }
+        }
+        self.history.end_group();
+        changed
+    }
+
+    // every voxel whose position lies within radius of the centre, returns how many blocks changed
+    pub fn fill_sphere(&mut self, centre: [f32; 3], radius: f32, block_type: BlockType) -> usize {
+        let radius = radius.max(0.0);
+        let low: [i32; 3] = std::array::from_fn(|axis| (centre[axis] - radius).ceil() as i32);
+        let high: [i32; 3] = std::array::from_fn(|axis| (centre[axis] + radius).floor() as i32);
+        let mut changed = 0;
+        self.history.begin_group();
+        for x in low[0]..=high[0] {
+            for y in low[1].max(0)..=high[1].min(CHUNK_SIZE as i32 - 1) {
+                for z in low[2]..=high[2] {
+                    let offset = [x as f32 - centre[0], y as f32 - centre[1], z as f32 - centre[2]];
+                    if offset.iter().map(|o| o * o).sum::<f32>() > radius * radius {
+                        continue;
+                    }
+                    if self.set_block([x, y, z], block_type) {
+                        changed += 1;
+                    }
+                }
+            }
+        }
+        self.history.end_group();
+        changed
+    }
+
+    pub fn set_storage_kind(&mut self, kind: StorageKind) {
+        for chunk in self.chunk_buffer.iter_mut() {
+            chunk.convert_storage(kind);
+        }
+    }
+
+    // only dirty chunks are meshed again, the others come from the cache, pending light updates go first
+    // returns the opaque mesh and the translucent one (water, glass, ice) separately
+    pub fn build_mesh_parts(&mut self) -> (MeshBuffers, MeshBuffers) {
+        light::relight(self);
+        for chunk_index in 0..self.chunk_buffer.len() {
+            if !self.chunk_buffer[chunk_index].dirty {
+                continue;
+            }
+            // edits since the last mesh may have left palette entries unused
+            self.chunk_buffer[chunk_index].blocks.compact();
+            let (vertices, indices, translucent_vertices, translucent_indices) =
+                self.chunk_buffer[chunk_index].build_mesh(
+                    0,
+                    0,
+                    &|world_position| self.get_block(world_position),
+                    &|world_position| Some((self.get_sky_light(world_position)?, self.get_block_light(world_position)?)),
+                );
+            self.mesh_cache[chunk_index] = ChunkMesh {
+                vertices,
+                indices,
+                translucent_vertices,
+                translucent_indices,
+            };
+            self.chunk_buffer[chunk_index].dirty = false;
+        }
         let mut vertices: Vec<Vertex> = Vec::new();
         let mut indices: Vec<u32> = Vec::new();
-        let mut water_vertices: Vec<Vertex> = Vec::new();
-        let mut water_indices: Vec<u32> = Vec::new();
-        for chunk in self.chunk_buffer.iter() {
-            let (chunk_vertices, chunk_indices,chunk_water_vertices,chunk_water_indices) = chunk.build_mesh(vertices.len() as u32, water_vertices.len() as u32);
-            vertices.extend(chunk_vertices.iter());
-            indices.extend(chunk_indices.iter());
-            water_vertices.extend(chunk_water_vertices.iter());
-            water_indices.extend(chunk_water_indices.iter());
-        }
-        let reindexed:Vec<u32> = water_indices.iter().map(|index| *index + vertices.len() as u32).collect();
-        vertices.extend(water_vertices.iter());
-        indices.extend(reindexed.iter());
+        let mut translucent_vertices: Vec<Vertex> = Vec::new();
+        let mut translucent_indices: Vec<u32> = Vec::new();
+        for mesh in self.mesh_cache.iter() {
+            let offset = vertices.len() as u32;
+            vertices.extend(mesh.vertices.iter());
+            indices.extend(mesh.indices.iter().map(|index| *index + offset));
+            let offset = translucent_vertices.len() as u32;
+            translucent_vertices.extend(mesh.translucent_vertices.iter());
+            translucent_indices.extend(mesh.translucent_indices.iter().map(|index| *index + offset));
+        }
+        ((vertices, indices), (translucent_vertices, translucent_indices))
+    }
+
+    pub fn build_mesh(&mut self) -> MeshBuffers {
+        let ((mut vertices, mut indices), (translucent_vertices, translucent_indices)) = self.build_mesh_parts();
+        // translucent faces go last so they blend over the opaque terrain
+        let offset = vertices.len() as u32;
+        vertices.extend(translucent_vertices.iter());
+        indices.extend(translucent_indices.iter().map(|index| *index + offset));
         (vertices, indices)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fill_sphere_is_one_undo_step_and_marks_chunks_dirty() {
+        let mut region = Region::empty([0, 0]);
+        for chunk in region.chunk_buffer.iter_mut() {
+            chunk.dirty = false;
+        }
+        let changed = region.fill_sphere([0.0, 10.0, 0.0], 2.0, BlockType::STONE);
+        // 33 voxels lie within two blocks of a voxel centre
+        assert_eq!(changed, 33);
+        assert_eq!(region.get_block([2, 10, 0]), Some(BlockType::STONE));
+        assert_eq!(region.get_block([0, 8, 0]), Some(BlockType::STONE));
+        assert_eq!(region.get_block([1, 11, 1]), Some(BlockType::STONE));
+        assert_eq!(region.get_block([2, 11, 0]), Some(BlockType::AIR));
+        // the sphere straddles the chunk corner at the origin
+        for (chunk_x, chunk_z) in [(0, 0), (-1, 0), (0, -1), (-1, -1)] {
+            assert!(region.chunk_buffer[region.chunk_index(chunk_x, chunk_z).unwrap()].dirty);
+        }
+
+        assert!(region.undo());
+        assert_eq!(region.get_block([0, 10, 0]), Some(BlockType::AIR));
+        assert_eq!(region.get_block([2, 10, 0]), Some(BlockType::AIR));
+        assert!(!region.undo());
+    }
+}
@@ -0,0 +1,330 @@
+use crate::block_registry::BlockRegistry;
+use crate::nbt::{self, Tag};
+use crate::region::Region;
+use crate::voxel::BlockType;
+use crate::world_save::invalid_data;
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+// Minecraft 1.20.1, editors use it to decide which block names to expect
+const DATA_VERSION: i32 = 3465;
+// written for blocks without a minecraft name in the registry, unknown names read back as stone too
+const FALLBACK_NAME: &str = "minecraft:stone";
+// far more than a schematic that fits the loaded region needs, a gzip bomb stops here
+const MAX_DECODED_LEN: usize = 64 * 1024 * 1024;
+
+// Sponge schematic, blocks are indexed x + z * width + y * width * length
+pub struct Schematic {
+    pub size: [i32; 3],
+    pub blocks: Vec<BlockType>,
+    // block names without a mapping, they were read as stone
+    pub unknown: Vec<String>,
+}
+
+fn block_for_state(state: &str) -> Option<BlockType> {
+    // block state properties like [snowy=false] do not change the mapping
+    let name = state.split('[').next().unwrap_or(state);
+    let name = if name.contains(':') {
+        name.to_string()
+    } else {
+        format!("minecraft:{}", name)
+    };
+    BlockRegistry::global()
+        .find_minecraft(&name)
+        .map(|definition| BlockType(definition.id))
+}
+
+fn read_varints(bytes: &[u8], count: usize) -> io::Result<Vec<usize>> {
+    // every value takes at least one byte, so the data bounds the allocation and not the claimed size
+    let mut values = Vec::with_capacity(count.min(bytes.len()));
+    let mut value = 0;
+    let mut shift = 0;
+    for byte in bytes.iter() {
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            values.push(value);
+            value = 0;
+            shift = 0;
+        } else {
+            shift += 7;
+            if shift > 28 {
+                return Err(invalid_data("block data varint too long"));
+            }
+        }
+    }
+    if values.len() != count {
+        return Err(invalid_data("block data does not match the schematic size"));
+    }
+    Ok(values)
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+// reads Sponge schematic versions 2 and 3, gzip compressed or plain
+pub fn parse(bytes: &[u8]) -> io::Result<Schematic> {
+    let mut decoded = Vec::new();
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        GzDecoder::new(bytes).take(MAX_DECODED_LEN as u64 + 1).read_to_end(&mut decoded)?;
+        if decoded.len() > MAX_DECODED_LEN {
+            return Err(invalid_data("schematic is too large"));
+        }
+    } else {
+        decoded = bytes.to_vec();
+    }
+    let (_, root) = nbt::read(&decoded)?;
+    // version 3 nests everything in a Schematic compound
+    let schematic = root.get("Schematic").unwrap_or(&root);
+    let version = schematic
+        .get("Version")
+        .and_then(Tag::as_i64)
+        .ok_or_else(|| invalid_data("missing schematic version"))?;
+    let (palette, data) = match version {
+        1 | 2 => (schematic.get("Palette"), schematic.get("BlockData")),
+        3 => {
+            let blocks = schematic.get("Blocks");
+            (
+                blocks.and_then(|blocks| blocks.get("Palette")),
+                blocks.and_then(|blocks| blocks.get("Data")),
+            )
+        }
+        _ => return Err(invalid_data("unsupported Sponge schematic version")),
+    };
+    let mut size = [0; 3];
+    for (axis, key) in ["Width", "Height", "Length"].iter().enumerate() {
+        // stored as unsigned shorts, so extents past 32767 read back the way export writes them
+        size[axis] = match schematic.get(key) {
+            Some(Tag::Short(extent)) => *extent as u16 as i32,
+            Some(tag) => {
+                let extent = tag.as_i64().ok_or_else(|| invalid_data("missing schematic size"))?;
+                u16::try_from(extent).map_err(|_| invalid_data("schematic size out of range"))? as i32
+            }
+            None => return Err(invalid_data("missing schematic size")),
+        };
+    }
+    let Some(Tag::Compound(palette)) = palette else {
+        return Err(invalid_data("missing block palette"));
+    };
+    let Some(Tag::ByteArray(data)) = data else {
+        return Err(invalid_data("missing block data"));
+    };
+    let mut unknown = Vec::new();
+    let mut palette_blocks: HashMap<usize, BlockType> = HashMap::new();
+    for (state, index) in palette.iter() {
+        let index = index
+            .as_i64()
+            .ok_or_else(|| invalid_data("palette entry is not a number"))?;
+        let block_type = block_for_state(state).unwrap_or_else(|| {
+            unknown.push(state.clone());
+            BlockType::STONE
+        });
+        palette_blocks.insert(index as usize, block_type);
+    }
+    let count = size
+        .iter()
+        .try_fold(1usize, |count, extent| count.checked_mul(*extent as usize))
+        .ok_or_else(|| invalid_data("schematic too large"))?;
+    let blocks = read_varints(data, count)?
+        .into_iter()
+        .map(|index| palette_blocks.get(&index).copied().unwrap_or(BlockType::STONE))
+        .collect();
+    Ok(Schematic {
+        size,
+        blocks,
+        unknown,
+    })
+}
+
+// places the schematic with its lowest corner at origin, air included, as one undo step
+pub fn import(region: &mut Region, schematic: &Schematic, origin: [i32; 3]) -> usize {
+    let [width, height, length] = schematic.size;
+    let mut changed = 0;
+    region.history.begin_group();
+    for y in 0..height {
+        for z in 0..length {
+            for x in 0..width {
+                let block_type = schematic.blocks[(x + z * width + y * width * length) as usize];
+                if region.set_block([origin[0] + x, origin[1] + y, origin[2] + z], block_type) {
+                    changed += 1;
+                }
+            }
+        }
+    }
+    region.history.end_group();
+    changed
+}
+
+// writes the inclusive box between min and max as a gzip compressed Sponge schematic
+pub fn export(region: &Region, min: [i32; 3], max: [i32; 3], version: i32) -> io::Result<Vec<u8>> {
+    if version != 2 && version != 3 {
+        return Err(invalid_data("only Sponge schematic versions 2 and 3 can be written"));
+    }
+    let low: [i32; 3] = std::array::from_fn(|axis| min[axis].min(max[axis]));
+    let high: [i32; 3] = std::array::from_fn(|axis| min[axis].max(max[axis]));
+    let size: [i32; 3] = std::array::from_fn(|axis| high[axis] - low[axis] + 1);
+    if size.iter().any(|axis_size| *axis_size > u16::MAX as i32) {
+        return Err(invalid_data("box is too large for a schematic"));
+    }
+    let mut names: Vec<String> = Vec::new();
+    // two of our blocks can share a Minecraft name, they share the palette entry too
+    let mut name_indices: HashMap<String, usize> = HashMap::new();
+    let mut block_indices: HashMap<BlockType, usize> = HashMap::new();
+    let mut data = Vec::new();
+    for y in 0..size[1] {
+        for z in 0..size[2] {
+            for x in 0..size[0] {
+                let block_type = region
+                    .get_block([low[0] + x, low[1] + y, low[2] + z])
+                    .unwrap_or(BlockType::AIR);
+                let index = match block_indices.get(&block_type) {
+                    Some(index) => *index,
+                    None => {
+                        let name = block_type
+                            .definition()
+                            .minecraft
+                            .first()
+                            .map_or(FALLBACK_NAME, |name| name.as_str());
+                        let index = *name_indices.entry(name.to_string()).or_insert_with(|| {
+                            names.push(name.to_string());
+                            names.len() - 1
+                        });
+                        block_indices.insert(block_type, index);
+                        index
+                    }
+                };
+                write_varint(&mut data, index);
+            }
+        }
+    }
+    let palette = Tag::Compound(
+        names
+            .iter()
+            .enumerate()
+            .map(|(index, name)| (name.clone(), Tag::Int(index as i32)))
+            .collect(),
+    );
+    let mut body = vec![
+        ("Version".to_string(), Tag::Int(version)),
+        ("DataVersion".to_string(), Tag::Int(DATA_VERSION)),
+        ("Width".to_string(), Tag::Short(size[0] as u16 as i16)),
+        ("Height".to_string(), Tag::Short(size[1] as u16 as i16)),
+        ("Length".to_string(), Tag::Short(size[2] as u16 as i16)),
+        ("Offset".to_string(), Tag::IntArray(vec![0, 0, 0])),
+    ];
+    let root = if version == 2 {
+        body.push(("PaletteMax".to_string(), Tag::Int(names.len() as i32)));
+        body.push(("Palette".to_string(), palette));
+        body.push(("BlockData".to_string(), Tag::ByteArray(data)));
+        body.push(("BlockEntities".to_string(), Tag::List(Vec::new())));
+        nbt::write("Schematic", &Tag::Compound(body))
+    } else {
+        body.push((
+            "Blocks".to_string(),
+            Tag::Compound(vec![
+                ("Palette".to_string(), palette),
+                ("Data".to_string(), Tag::ByteArray(data)),
+                ("BlockEntities".to_string(), Tag::List(Vec::new())),
+            ]),
+        ));
+        nbt::write("", &Tag::Compound(vec![("Schematic".to_string(), Tag::Compound(body))]))
+    };
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&root)?;
+    encoder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_region() -> Region {
+        let mut region = Region::empty([0, 0]);
+        region.set_block([0, 10, 0], BlockType::STONE);
+        region.set_block([2, 10, 1], BlockType::GRASS);
+        region.set_block([1, 12, 3], BlockType::GRANITE);
+        region.set_block([2, 11, 3], BlockType::WATER);
+        region
+    }
+
+    #[test]
+    fn export_parses_back_in_both_versions() {
+        let region = sample_region();
+        for version in [2, 3] {
+            let bytes = export(&region, [0, 10, 0], [2, 12, 3], version).unwrap();
+            let schematic = parse(&bytes).unwrap();
+            assert_eq!(schematic.size, [3, 3, 4]);
+            assert!(schematic.unknown.is_empty());
+
+            let mut imported = Region::empty([0, 0]);
+            import(&mut imported, &schematic, [0, 10, 0]);
+            for x in 0..3 {
+                for y in 10..13 {
+                    for z in 0..4 {
+                        assert_eq!(imported.get_block([x, y, z]), region.get_block([x, y, z]), "version {}", version);
+                    }
+                }
+            }
+        }
+        assert!(export(&region, [0, 10, 0], [2, 12, 3], 1).is_err());
+    }
+
+    #[test]
+    fn extents_past_the_signed_short_range_read_back() {
+        let region = Region::empty([0, 0]);
+        let bytes = export(&region, [0, 10, 0], [39_999, 10, 0], 2).unwrap();
+        let schematic = parse(&bytes).unwrap();
+        assert_eq!(schematic.size, [40_000, 1, 1]);
+        assert_eq!(schematic.blocks.len(), 40_000);
+    }
+
+    #[test]
+    fn varints_are_bounded_by_the_data() {
+        assert_eq!(read_varints(&[0x01, 0xac, 0x02, 0x7f], 3).unwrap(), vec![1, 300, 127]);
+        let mut bytes = Vec::new();
+        for value in [0, 127, 128, 16_383, 16_384, 2_000_000] {
+            write_varint(&mut bytes, value);
+        }
+        assert_eq!(read_varints(&bytes, 6).unwrap(), vec![0, 127, 128, 16_383, 16_384, 2_000_000]);
+
+        // the claimed size has to match what the data holds
+        assert!(read_varints(&[0x01, 0x02], 3).is_err());
+        assert!(read_varints(&[0x01, 0x02], 1).is_err());
+        assert!(read_varints(&[0x01], usize::MAX).is_err());
+        // unterminated and overlong values
+        assert!(read_varints(&[0x81], 1).is_err());
+        assert!(read_varints(&[0xff, 0xff, 0xff, 0xff, 0xff, 0x01], 1).is_err());
+    }
+
+    #[test]
+    fn block_names_map_to_registry_blocks() {
+        assert_eq!(block_for_state("minecraft:stone"), Some(BlockType::STONE));
+        assert_eq!(block_for_state("minecraft:cobblestone"), Some(BlockType::STONE));
+        assert_eq!(block_for_state("minecraft:grass_block[snowy=false]"), Some(BlockType::GRASS));
+        assert_eq!(block_for_state("deepslate[axis=y]"), Some(BlockType::SLATE));
+        assert_eq!(block_for_state("minecraft:cave_air"), Some(BlockType::AIR));
+        assert_eq!(block_for_state("minecraft:diamond_block"), None);
+        assert_eq!(block_for_state("othermod:stone"), None);
+    }
+
+    #[test]
+    fn oversized_gzip_streams_are_rejected() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+        encoder.write_all(&vec![0; MAX_DECODED_LEN + 1]).unwrap();
+        let error = parse(&encoder.finish().unwrap()).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "schematic is too large");
+    }
+}
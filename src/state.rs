@@ -7,7 +7,8 @@ use crate::{WINDOW_HEIGHT, WINDOW_WIDTH};
 use egui_wgpu::ScreenDescriptor;
 use std::sync::Arc;
 use wgpu::util::DeviceExt;
-use winit::event::WindowEvent;
+use winit::event::{ElementState, MouseButton, WindowEvent};
+use winit::keyboard::{Key, ModifiersState};
 use winit::window::Window;
 
 pub struct State {
@@ -24,6 +25,55 @@ pub struct State {
     pub len_indices: usize,
     pub surface_format: wgpu::TextureFormat,
     pub dolly_cam: DollyCamera,
+    pub highlight_pipeline: wgpu::RenderPipeline,
+    pub highlight_buffer: wgpu::Buffer,
+    pub highlight_vertices: u32,
+    pub cursor_position: [f32; 2],
+    pub clicks: Vec<MouseButton>,
+    pub held_buttons: Vec<MouseButton>,
+    pub modifiers: ModifiersState,
+    pub shortcuts: Vec<Shortcut>,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Shortcut {
+    Undo,
+    Redo,
+}
+
+// the cursor box and the selection box
+const HIGHLIGHT_BOXES: usize = 2;
+const BOX_VERTICES: usize = 24;
+
+// the 12 edges of an inclusive voxel box as a line list, pushed out a little so they do not z-fight the faces
+fn wireframe_box(min: [i32; 3], max: [i32; 3], color: [f32; 4]) -> Vec<Vertex> {
+    let extent = 0.505;
+    let mut vertices: Vec<Vertex> = Vec::new();
+    for axis in 0..3 {
+        for corner in 0..4 {
+            let mut start = [0.0; 3];
+            let mut end = [0.0; 3];
+            let others = [(axis + 1) % 3, (axis + 2) % 3];
+            for (bit, other) in others.iter().enumerate() {
+                let offset = if corner >> bit & 1 == 1 {
+                    max[*other] as f32 + extent
+                } else {
+                    min[*other] as f32 - extent
+                };
+                start[*other] = offset;
+                end[*other] = offset;
+            }
+            start[axis] = min[axis] as f32 - extent;
+            end[axis] = max[axis] as f32 + extent;
+            for point in [start, end] {
+                vertices.push(Vertex {
+                    position: [point[0], point[1], point[2], 1.0],
+                    color,
+                });
+            }
+        }
+    }
+    vertices
 }
 
 impl State {
@@ -172,6 +222,52 @@ impl State {
             }),
             multiview: None,
         });
+
+        let highlight_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Highlight pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader_module,
+                entry_point: "vs_main",
+                buffers: &[Vertex::desc()],
+            },
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader_module,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        });
+        let highlight_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Highlight Buffer"),
+            contents: bytemuck::cast_slice(&vec![[0.0f32; 8]; HIGHLIGHT_BOXES * BOX_VERTICES]),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
         Ok(Self {
             surface,
             queue,
@@ -186,6 +282,14 @@ impl State {
             len_indices: 0,
             surface_format,
             dolly_cam,
+            highlight_pipeline,
+            highlight_buffer,
+            highlight_vertices: 0,
+            cursor_position: [0.0, 0.0],
+            clicks: Vec::new(),
+            held_buttons: Vec::new(),
+            modifiers: ModifiersState::empty(),
+            shortcuts: Vec::new(),
         })
     }
 
@@ -257,6 +361,11 @@ impl State {
             render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
             render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
             render_pass.draw_indexed(0..self.len_indices as u32, 0, 0..1 as _);
+            if self.highlight_vertices > 0 {
+                render_pass.set_pipeline(&self.highlight_pipeline);
+                render_pass.set_vertex_buffer(0, self.highlight_buffer.slice(..));
+                render_pass.draw(0..self.highlight_vertices, 0..1);
+            }
         }
         gui_renderer.draw(
             &self.device,
@@ -277,13 +386,80 @@ impl State {
                 event,
                 is_synthetic,
             } => {
+                // only the shortcut presses are kept from the camera, releases always reach it
+                // so a movement key let go while Ctrl is down does not keep the camera moving
+                if self.modifiers.control_key() && event.state == ElementState::Pressed {
+                    // shift turns the logical key upper case
+                    let shortcut = match event.logical_key.as_ref() {
+                        Key::Character(c) if c.eq_ignore_ascii_case("z") && self.modifiers.shift_key() => {
+                            Some(Shortcut::Redo)
+                        }
+                        Key::Character(c) if c.eq_ignore_ascii_case("z") => Some(Shortcut::Undo),
+                        Key::Character(c) if c.eq_ignore_ascii_case("y") => Some(Shortcut::Redo),
+                        _ => None,
+                    };
+                    if let Some(shortcut) = shortcut {
+                        self.shortcuts.push(shortcut);
+                        return true;
+                    }
+                }
                 self.dolly_cam.process_cam_input(event);
                 true
             }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = [position.x as f32, position.y as f32];
+                true
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button,
+                ..
+            } => {
+                self.clicks.push(*button);
+                if !self.held_buttons.contains(button) {
+                    self.held_buttons.push(*button);
+                }
+                true
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Released,
+                button,
+                ..
+            } => {
+                self.held_buttons.retain(|held| held != button);
+                true
+            }
             _ => false,
         }
     }
 
+    // outlines the targeted voxel in black and the selection, if any, in yellow
+    pub fn set_highlight(&mut self, cursor: Option<[i32; 3]>, selection: Option<([i32; 3], [i32; 3])>) {
+        let mut vertices: Vec<Vertex> = Vec::new();
+        if let Some(position) = cursor {
+            vertices.extend(wireframe_box(position, position, [0.0, 0.0, 0.0, 1.0]));
+        }
+        if let Some((min, max)) = selection {
+            vertices.extend(wireframe_box(min, max, [1.0, 0.85, 0.0, 1.0]));
+        }
+        self.highlight_vertices = vertices.len() as u32;
+        if !vertices.is_empty() {
+            self.queue
+                .write_buffer(&self.highlight_buffer, 0, bytemuck::cast_slice(&vertices));
+        }
+    }
+
+    pub fn cursor_ray(&self, window_size: winit::dpi::PhysicalSize<u32>) -> ([f32; 3], [f32; 3]) {
+        self.camera_uniform.cursor_ray(
+            self.cursor_position,
+            [window_size.width as f32, window_size.height as f32],
+        )
+    }
+
     pub fn update(&mut self, dt: instant::Duration) {
         // bad idea to set static delta time but dolly cam smoothness gets ruined with variable delta time
         self.camera_uniform
@@ -0,0 +1,228 @@
+use crate::chunk::{Chunk, CHUNK_SIZE};
+use crate::octree::{OctreeHit, SparseOctree};
+use crate::palette::PaletteStorage;
+use crate::raycast;
+use crate::region::Region;
+use crate::voxel::{BlockType, Voxel};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum StorageKind {
+    Palette,
+    Octree,
+}
+
+pub enum ChunkStorage {
+    Palette(PaletteStorage),
+    Octree(SparseOctree),
+}
+
+impl ChunkStorage {
+    pub fn from_dense(blocks_vector: &[Voxel], kind: StorageKind) -> Self {
+        match kind {
+            StorageKind::Palette => ChunkStorage::Palette(PaletteStorage::from_dense(blocks_vector)),
+            StorageKind::Octree => ChunkStorage::Octree(SparseOctree::from_dense(
+                blocks_vector,
+                CHUNK_SIZE,
+                &Chunk::linearize,
+            )),
+        }
+    }
+
+    pub fn to_dense(&self) -> Vec<Voxel> {
+        match self {
+            ChunkStorage::Palette(palette) => palette.to_dense(),
+            ChunkStorage::Octree(octree) => octree.to_dense(&Chunk::linearize),
+        }
+    }
+
+    pub fn kind(&self) -> StorageKind {
+        match self {
+            ChunkStorage::Palette(_) => StorageKind::Palette,
+            ChunkStorage::Octree(_) => StorageKind::Octree,
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> BlockType {
+        match self {
+            ChunkStorage::Palette(palette) => palette.get(Chunk::linearize(x, y, z)),
+            ChunkStorage::Octree(octree) => octree.get(x, y, z),
+        }
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize, block_type: BlockType) {
+        match self {
+            ChunkStorage::Palette(palette) => palette.set(Chunk::linearize(x, y, z), block_type),
+            ChunkStorage::Octree(octree) => octree.set(x, y, z, block_type),
+        }
+    }
+
+    // the octree already merges uniform subtrees as it is written, only the palette needs a pass
+    pub fn compact(&mut self) {
+        if let ChunkStorage::Palette(palette) = self {
+            palette.compact();
+        }
+    }
+
+    // the octree skips whole empty subtrees, the palette only skips all-air chunks
+    pub fn for_each_active(&self, visit: &mut dyn FnMut(usize, usize, usize, BlockType)) {
+        match self {
+            ChunkStorage::Palette(palette) => {
+                if palette.is_uniform() && palette.palette()[0] == BlockType::AIR {
+                    return;
+                }
+                for x in 0..CHUNK_SIZE {
+                    for y in 0..CHUNK_SIZE {
+                        for z in 0..CHUNK_SIZE {
+                            let block_type = palette.get(Chunk::linearize(x, y, z));
+                            if block_type != BlockType::AIR {
+                                visit(x, y, z, block_type);
+                            }
+                        }
+                    }
+                }
+            }
+            ChunkStorage::Octree(octree) => octree.for_each_leaf(&mut |min, size, block_type| {
+                if block_type == BlockType::AIR {
+                    return;
+                }
+                for x in min[0]..min[0] + size {
+                    for y in min[1]..min[1] + size {
+                        for z in min[2]..min[2] + size {
+                            visit(x, y, z, block_type);
+                        }
+                    }
+                }
+            }),
+        }
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        match self {
+            ChunkStorage::Palette(palette) => palette.memory_usage(),
+            ChunkStorage::Octree(octree) => octree.memory_usage(),
+        }
+    }
+}
+
+// every backend goes through the same DDA, only the lookup of whether a voxel stops the ray differs
+fn chunk_raycast(
+    is_solid: &dyn Fn(usize, usize, usize) -> bool,
+    origin: [f32; 3],
+    direction: [f32; 3],
+    max_distance: f32,
+) -> Option<f32> {
+    let size = CHUNK_SIZE as i32;
+    raycast::traverse(origin, direction, max_distance, &mut |entered| {
+        let [x, y, z] = entered.cell;
+        [x, y, z].iter().all(|c| (0..size).contains(c)) && is_solid(x as usize, y as usize, z as usize)
+    })
+    .map(|hit| hit.distance)
+}
+
+// prints memory use and timings of dense, palette and octree storage for every chunk of the region
+pub fn benchmark(region: &Region) {
+    let rays: Vec<([f32; 3], [f32; 3])> = (0..256)
+        .map(|i| {
+            let angle = i as f32 / 256.0 * std::f32::consts::TAU;
+            let direction = [angle.cos(), -1.0, angle.sin()].map(|c| c / 2f32.sqrt());
+            ([32.0, 63.5, 32.0], direction)
+        })
+        .collect();
+    let mut dense_memory = 0;
+    let mut dense_rays = std::time::Duration::ZERO;
+    let mut dense_hits = 0;
+    for chunk in region.chunk_buffer.iter() {
+        let blocks_vector: Vec<BlockType> = chunk.blocks.to_dense().iter().map(|voxel| voxel.block_type).collect();
+        dense_memory += blocks_vector.capacity() * std::mem::size_of::<BlockType>();
+        let start = instant::Instant::now();
+        let is_solid = |x, y, z| blocks_vector[Chunk::linearize(x, y, z)] != BlockType::AIR;
+        for (origin, direction) in rays.iter() {
+            if chunk_raycast(&is_solid, *origin, *direction, 128.0).is_some() {
+                dense_hits += 1;
+            }
+        }
+        dense_rays += start.elapsed();
+    }
+    println!("dense: {} KiB, rays {:?} ({} hits)", dense_memory / 1024, dense_rays, dense_hits);
+    let light_memory: usize = region
+        .chunk_buffer
+        .iter()
+        .map(|chunk| chunk.sky_light.memory_usage() + chunk.block_light.memory_usage())
+        .sum();
+    println!("light: {} KiB", light_memory / 1024);
+
+    for kind in [StorageKind::Palette, StorageKind::Octree] {
+        let mut memory = 0;
+        let mut mesh_time = std::time::Duration::ZERO;
+        let mut read_time = std::time::Duration::ZERO;
+        let mut ray_time = std::time::Duration::ZERO;
+        let mut ray_hits = 0;
+        let mut ray_distance = 0.0;
+        // the octree's own slab traversal, timed on its own and checked against the DDA
+        let mut slab_time = std::time::Duration::ZERO;
+        let mut slab_mismatches = 0;
+        for chunk in region.chunk_buffer.iter() {
+            let chunk = Chunk {
+                world_position: chunk.world_position,
+                water_level: chunk.water_level,
+                fluid_levels: chunk.fluid_levels.clone(),
+                sky_light: chunk.sky_light.clone(),
+                block_light: chunk.block_light.clone(),
+                blocks: ChunkStorage::from_dense(&chunk.blocks.to_dense(), kind),
+                modified: false,
+                dirty: true,
+            };
+            memory += chunk.blocks.memory_usage();
+            let start = instant::Instant::now();
+            let mut active = 0;
+            chunk.blocks.for_each_active(&mut |_, _, _, _| active += 1);
+            read_time += start.elapsed();
+            let start = instant::Instant::now();
+            let _ = chunk.build_mesh(0, 0, &|_| None, &|_| None);
+            mesh_time += start.elapsed();
+            let is_solid = |x, y, z| chunk.blocks.get(x, y, z) != BlockType::AIR;
+            let mut distances = Vec::with_capacity(rays.len());
+            let start = instant::Instant::now();
+            for (origin, direction) in rays.iter() {
+                distances.push(chunk_raycast(&is_solid, *origin, *direction, 128.0));
+            }
+            ray_time += start.elapsed();
+            for distance in distances.iter().flatten() {
+                ray_hits += 1;
+                ray_distance += distance;
+            }
+            if let ChunkStorage::Octree(octree) = &chunk.blocks {
+                let start = instant::Instant::now();
+                let hits: Vec<Option<OctreeHit>> = rays
+                    .iter()
+                    .map(|(origin, direction)| octree.raycast(*origin, *direction, 128.0))
+                    .collect();
+                slab_time += start.elapsed();
+                slab_mismatches += hits
+                    .iter()
+                    .zip(distances.iter())
+                    .filter(|(slab, dda)| match (slab, dda) {
+                        (Some(slab), Some(dda)) => {
+                            let [x, y, z] = slab.position;
+                            (slab.distance - dda).abs() > 1e-3 || octree.get(x, y, z) != slab.block_type
+                        }
+                        (slab, dda) => slab.is_some() != dda.is_some(),
+                    })
+                    .count();
+            }
+        }
+        println!(
+            "{:?}: {} KiB, traversal {:?}, meshing {:?}, rays {:?} ({} hits, mean distance {:.2})",
+            kind,
+            memory / 1024,
+            read_time,
+            mesh_time,
+            ray_time,
+            ray_hits,
+            ray_distance / ray_hits.max(1) as f32,
+        );
+        if kind == StorageKind::Octree {
+            println!("Octree slab rays: {:?} ({} disagreeing with the DDA)", slab_time, slab_mismatches);
+        }
+    }
+}
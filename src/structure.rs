@@ -0,0 +1,393 @@
+use crate::chunk::{Chunk, CHUNK_SIZE};
+use crate::continent::ContinentCells;
+use crate::generation_params::GenerationParams;
+use crate::voxel::{BlockType, Voxel};
+
+use noise::permutationtable::PermutationTable;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const BUILTIN_TEMPLATES: [&str; 3] = [
+    include_str!("../structures/ruins.structure"),
+    include_str!("../structures/tower.structure"),
+    include_str!("../structures/village.structure"),
+];
+
+pub struct PlacementRule {
+    // structures are placed at most once per spacing x spacing cell of the grid
+    pub spacing: i32,
+    pub chance: f64,
+    // largest height difference allowed under the footprint
+    pub max_slope: i32,
+}
+
+pub struct StructureTemplate {
+    pub size: [usize; 3],
+    // None keeps whatever the terrain generated in that cell
+    pub blocks: Vec<Option<BlockType>>,
+    pub foundation: BlockType,
+    pub placement: PlacementRule,
+}
+
+pub struct StructurePlacement {
+    pub template_index: usize,
+    pub origin: [i32; 3],
+}
+
+impl StructureTemplate {
+    pub fn builtin() -> Vec<StructureTemplate> {
+        BUILTIN_TEMPLATES
+            .iter()
+            .map(|source| Self::parse(source).unwrap())
+            .collect()
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> Option<BlockType> {
+        self.blocks[y * self.size[0] * self.size[2] + z * self.size[0] + x]
+    }
+
+    // header of `key: value` lines, a `palette:` of `char = block` lines and
+    // `layers:` given bottom to top, separated by `---`, rows along z and columns along x
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut name = String::new();
+        let mut spacing = 64;
+        let mut chance = 1.0;
+        let mut max_slope = 2;
+        let mut foundation = BlockType::STONE;
+        let mut palette: Vec<(char, Option<BlockType>)> = vec![(' ', None)];
+        let mut layers: Vec<Vec<&str>> = Vec::new();
+        let mut section = "";
+        for line in source.lines() {
+            if section == "layers" {
+                if line.trim() == "---" {
+                    layers.push(Vec::new());
+                } else if let Some(layer) = layers.last_mut() {
+                    if !line.is_empty() {
+                        layer.push(line);
+                    }
+                }
+                continue;
+            }
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            if section == "palette" {
+                if let Some((symbol, block)) = trimmed.split_once('=') {
+                    let symbol = symbol.trim().chars().next().ok_or("empty palette symbol")?;
+                    let block = BlockType::from_name(block.trim())
+                        .ok_or(format!("unknown block {}", block.trim()))?;
+                    palette.push((symbol, Some(block)));
+                    continue;
+                }
+            }
+            let (key, value) = trimmed
+                .split_once(':')
+                .ok_or(format!("unexpected line {}", trimmed))?;
+            let value = value.trim();
+            match key {
+                "name" => name = value.to_string(),
+                // the spacing sizes the placement grid, so it has to be at least one block
+                "spacing" => {
+                    spacing = value
+                        .parse()
+                        .ok()
+                        .filter(|spacing| *spacing > 0)
+                        .ok_or("invalid spacing")?
+                }
+                "chance" => chance = value.parse().map_err(|_| "invalid chance")?,
+                "max_slope" => max_slope = value.parse().map_err(|_| "invalid max_slope")?,
+                "foundation" => {
+                    foundation =
+                        BlockType::from_name(value).ok_or(format!("unknown block {}", value))?
+                }
+                "palette" | "layers" => section = key,
+                _ => return Err(format!("unknown key {}", key)),
+            }
+        }
+        let depth = layers.first().map_or(0, |layer| layer.len());
+        let width = layers
+            .first()
+            .and_then(|layer| layer.first())
+            .map_or(0, |row| row.chars().count());
+        if width == 0 || depth == 0 {
+            return Err(format!("structure {} has no layers", name));
+        }
+        let mut blocks = Vec::with_capacity(width * depth * layers.len());
+        for layer in layers.iter() {
+            if layer.len() != depth {
+                return Err(format!("structure {} has uneven layers", name));
+            }
+            for row in layer.iter() {
+                if row.chars().count() != width {
+                    return Err(format!("structure {} has uneven rows", name));
+                }
+                for symbol in row.chars() {
+                    let block = palette
+                        .iter()
+                        .find(|(palette_symbol, _)| *palette_symbol == symbol)
+                        .ok_or(format!("unknown symbol {}", symbol))?
+                        .1;
+                    blocks.push(block);
+                }
+            }
+        }
+        Ok(Self {
+            size: [width, layers.len(), depth],
+            blocks,
+            foundation,
+            placement: PlacementRule {
+                spacing,
+                chance,
+                max_slope,
+            },
+        })
+    }
+}
+
+fn cell_seed(seed: u32, template_index: usize, cell_x: i32, cell_z: i32) -> u64 {
+    (seed as u64)
+        ^ (template_index as u64).wrapping_mul(0xD6E8_FEB8_6659_FD93)
+        ^ (cell_x as i64 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (cell_z as i64 as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+}
+
+fn height_at(
+    x: i32,
+    z: i32,
+    params: &GenerationParams,
+    perm_table: &PermutationTable,
+    continent_cells: &ContinentCells,
+) -> i32 {
+    Chunk::surface_height(
+        x as f64 / CHUNK_SIZE as f64,
+        z as f64 / CHUNK_SIZE as f64,
+        params,
+        perm_table,
+        continent_cells,
+    ) as i32
+}
+
+// the placement of a grid cell only depends on the seed and the height field,
+// so every chunk the structure overlaps agrees on where it stands
+fn placement_for_cell(
+    template_index: usize,
+    template: &StructureTemplate,
+    cell: [i32; 2],
+    params: &GenerationParams,
+    perm_table: &PermutationTable,
+    continent_cells: &ContinentCells,
+) -> Option<StructurePlacement> {
+    let rule = &template.placement;
+    let mut rng = StdRng::seed_from_u64(cell_seed(params.seed, template_index, cell[0], cell[1]));
+    if rng.gen::<f64>() >= rule.chance {
+        return None;
+    }
+    let free_x = (rule.spacing - template.size[0] as i32).max(1);
+    let free_z = (rule.spacing - template.size[2] as i32).max(1);
+    let x = cell[0] * rule.spacing + rng.gen_range(0..free_x);
+    let z = cell[1] * rule.spacing + rng.gen_range(0..free_z);
+    let corners = [
+        [x, z],
+        [x + template.size[0] as i32 - 1, z],
+        [x, z + template.size[2] as i32 - 1],
+        [x + template.size[0] as i32 - 1, z + template.size[2] as i32 - 1],
+        [x + template.size[0] as i32 / 2, z + template.size[2] as i32 / 2],
+    ];
+    let heights: Vec<i32> = corners
+        .iter()
+        .map(|corner| height_at(corner[0], corner[1], params, perm_table, continent_cells))
+        .collect();
+    let min_height = *heights.iter().min().unwrap();
+    let max_height = *heights.iter().max().unwrap();
+    if max_height - min_height > rule.max_slope || min_height <= params.water_level as i32 {
+        return None;
+    }
+    let base_height = heights.iter().sum::<i32>() / heights.len() as i32;
+    if base_height + template.size[1] as i32 >= CHUNK_SIZE as i32 {
+        return None;
+    }
+    Some(StructurePlacement {
+        template_index,
+        origin: [x, base_height + 1, z],
+    })
+}
+
+pub fn placements_overlapping_chunk(
+    templates: &[StructureTemplate],
+    world_position: [f32; 3],
+    params: &GenerationParams,
+    perm_table: &PermutationTable,
+    continent_cells: &ContinentCells,
+) -> Vec<StructurePlacement> {
+    let chunk_min = [
+        world_position[0] as i32 * CHUNK_SIZE as i32,
+        world_position[2] as i32 * CHUNK_SIZE as i32,
+    ];
+    let chunk_max = [chunk_min[0] + CHUNK_SIZE as i32, chunk_min[1] + CHUNK_SIZE as i32];
+    let mut placements = Vec::new();
+    for (template_index, template) in templates.iter().enumerate() {
+        let spacing = template.placement.spacing;
+        let first_cell = [
+            (chunk_min[0] - spacing).div_euclid(spacing),
+            (chunk_min[1] - spacing).div_euclid(spacing),
+        ];
+        let last_cell = [chunk_max[0].div_euclid(spacing), chunk_max[1].div_euclid(spacing)];
+        for cell_x in first_cell[0]..=last_cell[0] {
+            for cell_z in first_cell[1]..=last_cell[1] {
+                let Some(placement) = placement_for_cell(
+                    template_index,
+                    template,
+                    [cell_x, cell_z],
+                    params,
+                    perm_table,
+                    continent_cells,
+                ) else {
+                    continue;
+                };
+                let overlaps = placement.origin[0] < chunk_max[0]
+                    && placement.origin[0] + template.size[0] as i32 > chunk_min[0]
+                    && placement.origin[2] < chunk_max[1]
+                    && placement.origin[2] + template.size[2] as i32 > chunk_min[1];
+                if overlaps {
+                    placements.push(placement);
+                }
+            }
+        }
+    }
+    placements
+}
+
+pub fn place_structures(
+    blocks_vector: &mut [Voxel],
+    world_position: [f32; 3],
+    column_heights: &[usize],
+    templates: &[StructureTemplate],
+    params: &GenerationParams,
+    perm_table: &PermutationTable,
+    continent_cells: &ContinentCells,
+) {
+    let chunk_origin = [
+        world_position[0] as i32 * CHUNK_SIZE as i32,
+        world_position[2] as i32 * CHUNK_SIZE as i32,
+    ];
+    let placements =
+        placements_overlapping_chunk(templates, world_position, params, perm_table, continent_cells);
+    for placement in placements {
+        let template = &templates[placement.template_index];
+        for tx in 0..template.size[0] {
+            for tz in 0..template.size[2] {
+                let local_x = placement.origin[0] + tx as i32 - chunk_origin[0];
+                let local_z = placement.origin[2] + tz as i32 - chunk_origin[1];
+                if local_x < 0
+                    || local_z < 0
+                    || local_x >= CHUNK_SIZE as i32
+                    || local_z >= CHUNK_SIZE as i32
+                {
+                    continue;
+                }
+                let (x, z) = (local_x as usize, local_z as usize);
+                let base_y = placement.origin[1] as usize;
+                // fill the gap between the terrain and the structure base
+                for y in column_heights[x * CHUNK_SIZE + z]..base_y {
+                    blocks_vector[Chunk::linearize(x, y, z)] = Voxel::new(template.foundation);
+                }
+                // and carve away hillside that would bury it
+                let top_y = base_y + template.size[1];
+                for y in top_y..=column_heights[x * CHUNK_SIZE + z].min(CHUNK_SIZE - 1) {
+                    blocks_vector[Chunk::linearize(x, y, z)] = Voxel::new(BlockType::AIR);
+                }
+                for ty in 0..template.size[1] {
+                    if let Some(block_type) = template.get(tx, ty, tz) {
+                        blocks_vector[Chunk::linearize(x, base_y + ty, z)] = Voxel::new(block_type);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 30 blocks wide on a 20 block grid, so every placement starts on its cell corner
+    const WIDE_WALL: &str = "name: wall\nspacing: 20\nchance: 1.0\nmax_slope: 64\nfoundation: dirt\n\
+                             palette:\n  # = stone\nlayers:\n---\n##############################\n";
+
+    #[test]
+    fn builtin_templates_parse() {
+        let templates = StructureTemplate::builtin();
+        assert_eq!(templates.len(), BUILTIN_TEMPLATES.len());
+        assert!(templates.iter().all(|template| template.placement.spacing > 0));
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        let template = |header: &str, layers: &str| {
+            StructureTemplate::parse(&format!("{}palette:\n  # = stone\nlayers:\n{}", header, layers))
+        };
+        assert!(template("", "---\n##\n##\n").is_ok());
+        assert_eq!(template("spacing: 0\n", "---\n#\n").err().unwrap(), "invalid spacing");
+        assert_eq!(template("spacing: -16\n", "---\n#\n").err().unwrap(), "invalid spacing");
+        assert_eq!(template("spacing: wide\n", "---\n#\n").err().unwrap(), "invalid spacing");
+        assert_eq!(template("chance: often\n", "---\n#\n").err().unwrap(), "invalid chance");
+        assert_eq!(template("colour: red\n", "---\n#\n").err().unwrap(), "unknown key colour");
+        assert_eq!(template("foundation: cheese\n", "---\n#\n").err().unwrap(), "unknown block cheese");
+        assert!(template("", "").is_err());
+        assert!(template("", "---\n##\n#\n").is_err());
+        assert!(template("", "---\n##\n---\n##\n##\n").is_err());
+        assert!(template("", "---\n#x\n").is_err());
+    }
+
+    #[test]
+    fn placements_agree_across_a_chunk_border() {
+        let templates = vec![StructureTemplate::parse(WIDE_WALL).unwrap()];
+        let params = GenerationParams {
+            water_level: 0,
+            ..GenerationParams::default()
+        };
+        let perm_table = PermutationTable::new(params.seed);
+        let continent_cells = ContinentCells::new(&params, [0, 0]);
+        let placements = |chunk_x: f32| {
+            placements_overlapping_chunk(&templates, [chunk_x, 0.0, 0.0], &params, &perm_table, &continent_cells)
+                .into_iter()
+                .map(|placement| placement.origin)
+                .collect::<Vec<[i32; 3]>>()
+        };
+        let left = placements(0.0);
+        let right = placements(1.0);
+        assert_eq!(left, placements(0.0));
+        // the cell starting at x = 60 runs over into the next chunk and both chunks see it at the same height,
+        // up to x = 80 where the next cell may stack its own foundation over it
+        let crossing: Vec<&[i32; 3]> = left.iter().filter(|origin| origin[0] == 60).collect();
+        assert!(!crossing.is_empty());
+        for origin in crossing.iter() {
+            assert!(right.contains(origin));
+        }
+
+        let mut blocks = [
+            vec![Voxel::new(BlockType::AIR); CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE],
+            vec![Voxel::new(BlockType::AIR); CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE],
+        ];
+        let column_heights = vec![0; CHUNK_SIZE * CHUNK_SIZE];
+        for (chunk_x, blocks) in blocks.iter_mut().enumerate() {
+            place_structures(
+                blocks,
+                [chunk_x as f32, 0.0, 0.0],
+                &column_heights,
+                &templates,
+                &params,
+                &perm_table,
+                &continent_cells,
+            );
+        }
+        for origin in crossing {
+            let [_, y, z] = origin.map(|coordinate| coordinate as usize);
+            assert_eq!(blocks[0][Chunk::linearize(63, y, z)].block_type, BlockType::STONE);
+            assert_eq!(blocks[1][Chunk::linearize(0, y, z)].block_type, BlockType::STONE);
+            assert_eq!(blocks[1][Chunk::linearize(15, y, z)].block_type, BlockType::STONE);
+            assert_eq!(blocks[1][Chunk::linearize(0, y - 1, z)].block_type, BlockType::DIRT);
+        }
+    }
+}
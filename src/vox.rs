@@ -0,0 +1,536 @@
+use crate::block_registry::BlockRegistry;
+use crate::region::Region;
+use crate::voxel::{BlockType, Voxel};
+use crate::world_save::{invalid_data, ByteReader, ByteWriter};
+
+use std::collections::HashMap;
+use std::io;
+
+const VOX_MAGIC: &[u8; 4] = b"VOX ";
+const VOX_VERSION: u32 = 150;
+// MagicaVoxel caps every model at this many voxels per axis
+const MAX_MODEL_SIZE: i32 = 256;
+
+// MagicaVoxel is z-up, a voxel (x, y, z) there lands on (x, z, -y) here so nothing gets mirrored
+pub struct VoxModel {
+    pub size: [i32; 3],
+    pub voxels: Vec<([u8; 3], u8)>,
+}
+
+pub struct VoxFile {
+    pub models: Vec<VoxModel>,
+    // each placed model as (model index, translation of the model centre), from the scene graph
+    pub instances: Vec<(usize, [i32; 3])>,
+    // indexed by the color index stored with each voxel, entry 0 is unused
+    pub palette: [[u8; 4]; 256],
+}
+
+// the palette MagicaVoxel falls back to when a file has no RGBA chunk
+fn default_palette() -> [[u8; 4]; 256] {
+    let mut palette = [[0; 4]; 256];
+    let levels = [0xff, 0xcc, 0x99, 0x66, 0x33, 0x00];
+    let mut index = 1;
+    for r in levels {
+        for g in levels {
+            for b in levels {
+                if r == 0 && g == 0 && b == 0 {
+                    continue;
+                }
+                palette[index] = [r, g, b, 0xff];
+                index += 1;
+            }
+        }
+    }
+    let ramp = [0xee, 0xdd, 0xbb, 0xaa, 0x88, 0x77, 0x55, 0x44, 0x22, 0x11];
+    for channel in 0..4 {
+        for value in ramp {
+            palette[index] = match channel {
+                0 => [value, 0, 0, 0xff],
+                1 => [0, value, 0, 0xff],
+                2 => [0, 0, value, 0xff],
+                _ => [value, value, value, 0xff],
+            };
+            index += 1;
+        }
+    }
+    palette
+}
+
+fn read_chunk<'a>(reader: &mut ByteReader<'a>) -> io::Result<([u8; 4], ByteReader<'a>, ByteReader<'a>)> {
+    let id: [u8; 4] = reader.take(4)?.try_into().unwrap();
+    let content_len = reader.u32()? as usize;
+    let children_len = reader.u32()? as usize;
+    let content = ByteReader::new(reader.take(content_len)?);
+    let children = ByteReader::new(reader.take(children_len)?);
+    Ok((id, content, children))
+}
+
+fn read_string(reader: &mut ByteReader) -> io::Result<String> {
+    let len = reader.u32()? as usize;
+    Ok(String::from_utf8_lossy(reader.take(len)?).into_owned())
+}
+
+fn read_dict(reader: &mut ByteReader) -> io::Result<HashMap<String, String>> {
+    let count = reader.u32()?;
+    let mut dict = HashMap::new();
+    for _ in 0..count {
+        let key = read_string(reader)?;
+        let value = read_string(reader)?;
+        dict.insert(key, value);
+    }
+    Ok(dict)
+}
+
+enum SceneNode {
+    Transform { child: i32, translation: [i32; 3] },
+    Group { children: Vec<i32> },
+    Shape { models: Vec<i32> },
+}
+
+fn collect_instances(
+    nodes: &HashMap<i32, SceneNode>,
+    node: i32,
+    translation: [i32; 3],
+    depth: usize,
+    instances: &mut Vec<(usize, [i32; 3])>,
+) {
+    // a malformed graph could loop, real files are only a few levels deep
+    if depth > 64 {
+        return;
+    }
+    match nodes.get(&node) {
+        Some(SceneNode::Transform { child, translation: offset }) => {
+            let translation = std::array::from_fn(|axis| translation[axis].saturating_add(offset[axis]));
+            collect_instances(nodes, *child, translation, depth + 1, instances);
+        }
+        Some(SceneNode::Group { children }) => {
+            for child in children.iter() {
+                collect_instances(nodes, *child, translation, depth + 1, instances);
+            }
+        }
+        Some(SceneNode::Shape { models }) => {
+            for model in models.iter() {
+                instances.push((*model as usize, translation));
+            }
+        }
+        None => {}
+    }
+}
+
+pub fn parse(bytes: &[u8]) -> io::Result<VoxFile> {
+    let mut reader = ByteReader::new(bytes);
+    if reader.take(4)? != VOX_MAGIC {
+        return Err(invalid_data("not a MagicaVoxel file"));
+    }
+    let _version = reader.u32()?;
+    let (id, _, mut main) = read_chunk(&mut reader)?;
+    if &id != b"MAIN" {
+        return Err(invalid_data("missing MAIN chunk"));
+    }
+    let mut sizes: Vec<[i32; 3]> = Vec::new();
+    let mut models: Vec<VoxModel> = Vec::new();
+    let mut palette = default_palette();
+    let mut nodes: HashMap<i32, SceneNode> = HashMap::new();
+    while !main.is_empty() {
+        let (id, mut content, _) = read_chunk(&mut main)?;
+        match &id {
+            b"SIZE" => {
+                let mut size = [0; 3];
+                for axis_size in size.iter_mut() {
+                    *axis_size = match content.u32()? {
+                        value if value <= MAX_MODEL_SIZE as u32 => value as i32,
+                        _ => return Err(invalid_data("model larger than 256 voxels")),
+                    };
+                }
+                sizes.push(size);
+            }
+            b"XYZI" => {
+                let size = *sizes.last().ok_or_else(|| invalid_data("XYZI chunk without SIZE"))?;
+                let count = content.u32()? as usize;
+                // four bytes per voxel, a count the chunk cannot hold would otherwise size the allocation
+                if count.checked_mul(4).is_none_or(|len| len > content.remaining()) {
+                    return Err(invalid_data("XYZI chunk shorter than its voxel count"));
+                }
+                let mut voxels = Vec::with_capacity(count.min(content.remaining() / 4));
+                for _ in 0..count {
+                    let voxel = content.take(4)?;
+                    voxels.push(([voxel[0], voxel[1], voxel[2]], voxel[3]));
+                }
+                models.push(VoxModel { size, voxels });
+            }
+            b"RGBA" => {
+                // entry i holds color index i + 1, the last entry has no index
+                for color in palette.iter_mut().skip(1) {
+                    *color = content.take(4)?.try_into().unwrap();
+                }
+            }
+            b"nTRN" => {
+                let node = content.i32()?;
+                read_dict(&mut content)?;
+                let child = content.i32()?;
+                let _reserved = content.i32()?;
+                let _layer = content.i32()?;
+                let frames = content.u32()?;
+                let mut translation = [0; 3];
+                for frame in 0..frames {
+                    let attributes = read_dict(&mut content)?;
+                    if frame == 0 {
+                        if let Some(value) = attributes.get("_t") {
+                            let parts: Vec<i32> = value.split_whitespace().filter_map(|part| part.parse().ok()).collect();
+                            if parts.len() == 3 {
+                                translation = [parts[0], parts[1], parts[2]];
+                            }
+                        }
+                    }
+                }
+                nodes.insert(node, SceneNode::Transform { child, translation });
+            }
+            b"nGRP" => {
+                let node = content.i32()?;
+                read_dict(&mut content)?;
+                let count = content.u32()?;
+                let children = (0..count).map(|_| content.i32()).collect::<io::Result<Vec<i32>>>()?;
+                nodes.insert(node, SceneNode::Group { children });
+            }
+            b"nSHP" => {
+                let node = content.i32()?;
+                read_dict(&mut content)?;
+                let count = content.u32()?;
+                let mut shape_models = Vec::new();
+                for _ in 0..count {
+                    shape_models.push(content.i32()?);
+                    read_dict(&mut content)?;
+                }
+                nodes.insert(node, SceneNode::Shape { models: shape_models });
+            }
+            // PACK, materials, layers, cameras and notes carry nothing we use
+            _ => {}
+        }
+    }
+    let mut instances = Vec::new();
+    collect_instances(&nodes, 0, [0; 3], 0, &mut instances);
+    instances.retain(|(model, _)| *model < models.len());
+    if instances.is_empty() {
+        // files without a scene graph get their models laid out in a row
+        let mut x: i32 = 0;
+        for (index, model) in models.iter().enumerate() {
+            instances.push((index, [x.saturating_add(model.size[0] / 2), model.size[1] / 2, model.size[2] / 2]));
+            x = x.saturating_add(model.size[0] + 1);
+        }
+    }
+    Ok(VoxFile {
+        models,
+        instances,
+        palette,
+    })
+}
+
+fn nearest_block(color: [u8; 4]) -> BlockType {
+    let target = color.map(|channel| channel as f32 / 255.0);
+    BlockRegistry::global()
+        .iter()
+        .filter(|definition| definition.id != BlockType::AIR.id())
+        .min_by(|a, b| {
+            let distance = |rgba: [f32; 4]| (0..3).map(|i| (rgba[i] - target[i]).powi(2)).sum::<f32>();
+            distance(a.color).total_cmp(&distance(b.color))
+        })
+        .map_or(BlockType::STONE, |definition| BlockType(definition.id))
+}
+
+// places every model with its lowest corner at origin, as one undo step; translations come from
+// the file so the arithmetic saturates instead of overflowing
+pub fn import(region: &mut Region, vox: &VoxFile, origin: [i32; 3]) -> usize {
+    let blocks: Vec<BlockType> = vox.palette.iter().map(|color| nearest_block(*color)).collect();
+    let mut placed: Vec<([i32; 3], BlockType)> = Vec::new();
+    for (model, translation) in vox.instances.iter() {
+        let model = &vox.models[*model];
+        for (position, color_index) in model.voxels.iter() {
+            let world: [i32; 3] = std::array::from_fn(|axis| {
+                translation[axis].saturating_add(position[axis] as i32 - model.size[axis] / 2)
+            });
+            placed.push(([world[0], world[2], world[1].saturating_neg()], blocks[*color_index as usize]));
+        }
+    }
+    let Some(min) = (0..3)
+        .map(|axis| placed.iter().map(|(position, _)| position[axis]).min())
+        .collect::<Option<Vec<i32>>>()
+    else {
+        return 0;
+    };
+    let mut changed = 0;
+    region.history.begin_group();
+    for (position, block_type) in placed {
+        let world = std::array::from_fn(|axis| origin[axis].saturating_add(position[axis].saturating_sub(min[axis])));
+        if region.set_block(world, block_type) {
+            changed += 1;
+        }
+    }
+    region.history.end_group();
+    changed
+}
+
+fn write_chunk(writer: &mut ByteWriter, id: &[u8; 4], content: ByteWriter) {
+    writer.bytes.extend_from_slice(id);
+    writer.u32(content.bytes.len() as u32);
+    writer.u32(0);
+    writer.bytes.extend_from_slice(&content.bytes);
+}
+
+fn write_string(writer: &mut ByteWriter, value: &str) {
+    writer.u32(value.len() as u32);
+    writer.bytes.extend_from_slice(value.as_bytes());
+}
+
+// boxes wider than a MagicaVoxel model are split into several models placed by the scene graph
+pub fn export(region: &Region, min: [i32; 3], max: [i32; 3]) -> io::Result<Vec<u8>> {
+    let low: [i32; 3] = std::array::from_fn(|axis| min[axis].min(max[axis]));
+    let high: [i32; 3] = std::array::from_fn(|axis| min[axis].max(max[axis]));
+    // box extent in MagicaVoxel axes
+    let extent = [high[0] - low[0] + 1, high[2] - low[2] + 1, high[1] - low[1] + 1];
+    let tiles = extent.map(|size| (size + MAX_MODEL_SIZE - 1) / MAX_MODEL_SIZE);
+    let mut color_indices: HashMap<BlockType, u8> = HashMap::new();
+    let mut palette: Vec<BlockType> = Vec::new();
+    let mut models: Vec<([i32; 3], [i32; 3], Vec<u8>)> = Vec::new();
+    for tile_x in 0..tiles[0] {
+        for tile_y in 0..tiles[1] {
+            for tile_z in 0..tiles[2] {
+                let tile_min = [tile_x, tile_y, tile_z].map(|tile| tile * MAX_MODEL_SIZE);
+                let size: [i32; 3] = std::array::from_fn(|axis| (extent[axis] - tile_min[axis]).min(MAX_MODEL_SIZE));
+                let mut voxels: Vec<u8> = Vec::new();
+                for x in 0..size[0] {
+                    for y in 0..size[1] {
+                        for z in 0..size[2] {
+                            let position = [tile_min[0] + x, tile_min[1] + y, tile_min[2] + z];
+                            let world = [low[0] + position[0], low[1] + position[2], high[2] - position[1]];
+                            let Some(block_type) = region.get_block(world) else {
+                                continue;
+                            };
+                            if block_type == BlockType::AIR {
+                                continue;
+                            }
+                            let color_index = match color_indices.get(&block_type) {
+                                Some(index) => *index,
+                                None => {
+                                    if palette.len() == 255 {
+                                        return Err(invalid_data("more than 255 block types"));
+                                    }
+                                    palette.push(block_type);
+                                    color_indices.insert(block_type, palette.len() as u8);
+                                    palette.len() as u8
+                                }
+                            };
+                            voxels.extend_from_slice(&[x as u8, y as u8, z as u8, color_index]);
+                        }
+                    }
+                }
+                if !voxels.is_empty() {
+                    models.push((tile_min, size, voxels));
+                }
+            }
+        }
+    }
+
+    let mut children = ByteWriter::default();
+    for (_, size, voxels) in models.iter() {
+        let mut content = ByteWriter::default();
+        for axis_size in size {
+            content.u32(*axis_size as u32);
+        }
+        write_chunk(&mut children, b"SIZE", content);
+        let mut content = ByteWriter::default();
+        content.u32(voxels.len() as u32 / 4);
+        content.bytes.extend_from_slice(voxels);
+        write_chunk(&mut children, b"XYZI", content);
+    }
+    // root transform, a group, then one transform and shape per model
+    let mut content = ByteWriter::default();
+    content.i32(0);
+    content.u32(0);
+    content.i32(1);
+    content.i32(-1);
+    content.i32(-1);
+    content.u32(1);
+    content.u32(0);
+    write_chunk(&mut children, b"nTRN", content);
+    let mut content = ByteWriter::default();
+    content.i32(1);
+    content.u32(0);
+    content.u32(models.len() as u32);
+    for index in 0..models.len() {
+        content.i32(2 + 2 * index as i32);
+    }
+    write_chunk(&mut children, b"nGRP", content);
+    for (index, (tile_min, size, _)) in models.iter().enumerate() {
+        let node = 2 + 2 * index as i32;
+        let translation: [i32; 3] = std::array::from_fn(|axis| tile_min[axis] + size[axis] / 2);
+        let mut content = ByteWriter::default();
+        content.i32(node);
+        content.u32(0);
+        content.i32(node + 1);
+        content.i32(-1);
+        content.i32(0);
+        content.u32(1);
+        content.u32(1);
+        write_string(&mut content, "_t");
+        write_string(&mut content, &format!("{} {} {}", translation[0], translation[1], translation[2]));
+        write_chunk(&mut children, b"nTRN", content);
+        let mut content = ByteWriter::default();
+        content.i32(node + 1);
+        content.u32(0);
+        content.u32(1);
+        content.i32(index as i32);
+        content.u32(0);
+        write_chunk(&mut children, b"nSHP", content);
+    }
+    let mut content = ByteWriter::default();
+    for index in 0..256 {
+        let color = palette
+            .get(index)
+            .map_or([0.0, 0.0, 0.0, 1.0], |block_type| Voxel::get_rgb_for_type(*block_type));
+        for channel in color {
+            content.u8((channel * 255.0).round() as u8);
+        }
+    }
+    write_chunk(&mut children, b"RGBA", content);
+
+    let mut writer = ByteWriter::default();
+    writer.bytes.extend_from_slice(VOX_MAGIC);
+    writer.u32(VOX_VERSION);
+    writer.bytes.extend_from_slice(b"MAIN");
+    writer.u32(0);
+    writer.u32(children.bytes.len() as u32);
+    writer.bytes.extend_from_slice(&children.bytes);
+    Ok(writer.bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vox_file(chunks: ByteWriter) -> Vec<u8> {
+        let mut writer = ByteWriter::default();
+        writer.bytes.extend_from_slice(VOX_MAGIC);
+        writer.u32(VOX_VERSION);
+        writer.bytes.extend_from_slice(b"MAIN");
+        writer.u32(0);
+        writer.u32(chunks.bytes.len() as u32);
+        writer.bytes.extend_from_slice(&chunks.bytes);
+        writer.bytes
+    }
+
+    fn single_voxel_model(chunks: &mut ByteWriter, size: [u32; 3]) {
+        let mut content = ByteWriter::default();
+        for axis_size in size {
+            content.u32(axis_size);
+        }
+        write_chunk(chunks, b"SIZE", content);
+        let mut content = ByteWriter::default();
+        content.u32(1);
+        content.bytes.extend_from_slice(&[0, 0, 0, 1]);
+        write_chunk(chunks, b"XYZI", content);
+    }
+
+    #[test]
+    fn export_parses_and_imports_back() {
+        let placed = [
+            ([2, 10, 3], BlockType::STONE),
+            ([5, 10, 3], BlockType::DIRT),
+            ([2, 14, 7], BlockType::GRANITE),
+            ([4, 12, 5], BlockType::STONE),
+        ];
+        let mut region = Region::empty([0, 0]);
+        for (position, block_type) in placed {
+            region.set_block(position, block_type);
+        }
+        let bytes = export(&region, [2, 10, 3], [5, 14, 7]).unwrap();
+        let vox = parse(&bytes).unwrap();
+        assert_eq!(vox.models.len(), 1);
+        // the box in MagicaVoxel axes, z up
+        assert_eq!(vox.models[0].size, [4, 5, 5]);
+        assert_eq!(vox.models[0].voxels.len(), placed.len());
+        assert_eq!(vox.instances.len(), 1);
+
+        let mut imported = Region::empty([0, 0]);
+        assert_eq!(import(&mut imported, &vox, [2, 10, 3]), placed.len());
+        for x in 0..8 {
+            for y in 8..16 {
+                for z in 0..10 {
+                    assert_eq!(imported.get_block([x, y, z]), region.get_block([x, y, z]), "at {:?}", [x, y, z]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn models_above_the_size_limit_are_rejected() {
+        let mut chunks = ByteWriter::default();
+        single_voxel_model(&mut chunks, [4, 300, 4]);
+        let error = parse(&vox_file(chunks)).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let mut chunks = ByteWriter::default();
+        single_voxel_model(&mut chunks, [4, u32::MAX, 4]);
+        assert!(parse(&vox_file(chunks)).is_err());
+    }
+
+    #[test]
+    fn extreme_translations_do_not_overflow() {
+        let mut chunks = ByteWriter::default();
+        single_voxel_model(&mut chunks, [256, 256, 256]);
+        single_voxel_model(&mut chunks, [256, 256, 256]);
+        let mut content = ByteWriter::default();
+        content.i32(0);
+        content.u32(0);
+        content.i32(1);
+        content.i32(-1);
+        content.i32(-1);
+        content.u32(1);
+        content.u32(1);
+        write_string(&mut content, "_t");
+        write_string(&mut content, &format!("{} {} {}", i32::MAX, i32::MIN, i32::MAX));
+        write_chunk(&mut chunks, b"nTRN", content);
+        let mut content = ByteWriter::default();
+        content.i32(1);
+        content.u32(0);
+        content.u32(2);
+        content.i32(2);
+        content.i32(3);
+        write_chunk(&mut chunks, b"nGRP", content);
+        // the second model sits at the opposite end of the i32 range
+        let shapes = [
+            (2, 0, "2147483647 2147483647 2147483647"),
+            (3, 1, "-2147483648 -2147483648 -2147483648"),
+        ];
+        for (node, model, translation) in shapes {
+            let mut content = ByteWriter::default();
+            content.i32(node);
+            content.u32(0);
+            content.i32(node + 2);
+            content.i32(-1);
+            content.i32(0);
+            content.u32(1);
+            content.u32(1);
+            write_string(&mut content, "_t");
+            write_string(&mut content, translation);
+            write_chunk(&mut chunks, b"nTRN", content);
+            let mut content = ByteWriter::default();
+            content.i32(node + 2);
+            content.u32(0);
+            content.u32(1);
+            content.i32(model);
+            content.u32(0);
+            write_chunk(&mut chunks, b"nSHP", content);
+        }
+        let vox = parse(&vox_file(chunks)).unwrap();
+        // each sum clamps at the end of the range it ran into
+        assert_eq!(
+            vox.instances,
+            [(0, [i32::MAX, -1, i32::MAX]), (1, [-1, i32::MIN, -1])]
+        );
+        // placed relative to the lowest corner the two voxels land at x = i32::MAX and z = i32::MAX - 129,
+        // both far outside the region, so nothing is written
+        let mut region = Region::empty([0, 0]);
+        assert_eq!(import(&mut region, &vox, [0, 10, 0]), 0);
+        assert!(!region.undo());
+    }
+}
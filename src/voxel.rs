@@ -1,37 +1,77 @@
-#[derive(Copy, Clone, PartialEq)]
-pub enum BlockType {
-    Dirt,
-    Grass,
-    Stone,
-    None,
-    Water,
+use crate::block_registry::{BlockDefinition, BlockRegistry};
+
+// numeric block id, what each id means is defined by the block registry
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct BlockType(pub u8);
+
+impl BlockType {
+    pub const DIRT: BlockType = BlockType(0);
+    pub const GRASS: BlockType = BlockType(1);
+    pub const STONE: BlockType = BlockType(2);
+    pub const AIR: BlockType = BlockType(3);
+    pub const WATER: BlockType = BlockType(4);
+    pub const BEDROCK: BlockType = BlockType(5);
+    pub const GRANITE: BlockType = BlockType(6);
+    pub const SANDSTONE: BlockType = BlockType(7);
+    pub const SLATE: BlockType = BlockType(8);
+
+    // blocks the generator places by id, every registry has to define them
+    pub const BUILTIN: [(BlockType, &'static str); 9] = [
+        (BlockType::DIRT, "dirt"),
+        (BlockType::GRASS, "grass"),
+        (BlockType::STONE, "stone"),
+        (BlockType::AIR, "air"),
+        (BlockType::WATER, "water"),
+        (BlockType::BEDROCK, "bedrock"),
+        (BlockType::GRANITE, "granite"),
+        (BlockType::SANDSTONE, "sandstone"),
+        (BlockType::SLATE, "slate"),
+    ];
+
+    pub fn id(self) -> u8 {
+        self.0
+    }
+
+    pub fn from_id(id: u8) -> Option<BlockType> {
+        BlockRegistry::global()
+            .get(BlockType(id))
+            .map(|definition| BlockType(definition.id))
+    }
+
+    // unknown ids behave like air so a stray id never breaks meshing
+    pub fn definition(self) -> &'static BlockDefinition {
+        let registry = BlockRegistry::global();
+        registry
+            .get(self)
+            .unwrap_or_else(|| registry.get(BlockType::AIR).unwrap())
+    }
+
+    pub fn from_name(name: &str) -> Option<BlockType> {
+        BlockRegistry::global()
+            .find(name)
+            .map(|definition| BlockType(definition.id))
+    }
 }
 
 #[derive(Copy, Clone)]
 pub struct Voxel {
-    pub is_active: bool,
     pub block_type: BlockType,
 }
 
 impl Voxel {
-    pub fn new(is_active: bool, block_type: BlockType) -> Self {
-        Self {
-            is_active: is_active,
-            block_type: block_type,
-        }
+    pub fn new(block_type: BlockType) -> Self {
+        Self { block_type }
+    }
+
+    pub fn get_name_for_type(block_type: BlockType) -> &'static str {
+        BlockRegistry::global()
+            .get(block_type)
+            .map_or("unknown", |definition| definition.name.as_str())
     }
 
     pub fn get_rgb_for_type(block_type: BlockType) -> [f32; 4] {
-        match block_type {
-            BlockType::Dirt => [90.0 / 255.0, 63.0 / 255.0, 43.0 / 255.0, 1.0],
-            BlockType::Grass => [73.0 / 255.0, 115.0 / 255.0, 14.0 / 255.0, 1.0],
-            BlockType::Stone => [106.0 / 255.0, 98.0 / 255.0, 87.0 / 255.0, 1.0],
-            BlockType::Water => [95.0 / 255.0, 192.0 / 255.0, 237.0 / 255.0, 0.6],
-            _ => [0.0, 0.0, 0.0, 0.0],
-        }
+        BlockRegistry::global()
+            .get(block_type)
+            .map_or([0.0, 0.0, 0.0, 0.0], |definition| definition.color)
     }
 }
-
-// dirt 90, 63, 43
-// grass 121, 192, 90   73, 115, 14
-// stone 106, 98, 87s
@@ -0,0 +1,518 @@
+use crate::chunk::CHUNK_SIZE;
+use crate::light;
+use crate::generation_params::{ContinentMask, GenerationMode, GenerationParams};
+use crate::edit_delta::EditDelta;
+use crate::region::Region;
+use crate::storage::StorageKind;
+use crate::voxel::BlockType;
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+// files written with a newer minor version are still readable, unknown trailing data is skipped;
+// a newer major version means the layout changed and the world is rejected
+pub const FORMAT_MAJOR: u16 = 1;
+pub const FORMAT_MINOR: u16 = 0;
+
+const WORLD_MAGIC: &[u8; 4] = b"VXWD";
+const REGION_MAGIC: &[u8; 4] = b"VXRG";
+const WORLD_FILE: &str = "world.dat";
+const REGION_EXTENSION: &str = "vxr";
+// region files hold REGION_FILE_CHUNKS x REGION_FILE_CHUNKS chunks, each slot holding the player edits of one chunk
+const REGION_FILE_CHUNKS: i32 = 8;
+const REGION_FILE_SLOTS: usize = (REGION_FILE_CHUNKS * REGION_FILE_CHUNKS) as usize;
+// a slot with every voxel of the chunk edited and given a fluid level
+const MAX_CHUNK_EDITS_LEN: usize = 8 + 2 * (4 + CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE * 5);
+
+pub fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+#[derive(Default)]
+pub struct ByteWriter {
+    pub bytes: Vec<u8>,
+}
+
+impl ByteWriter {
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn i32(&mut self, value: i32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn f64(&mut self, value: f64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    // length prefixed so older readers can skip whatever newer writers append
+    pub fn block(&mut self, block: ByteWriter) {
+        self.u32(block.bytes.len() as u32);
+        self.bytes.extend_from_slice(&block.bytes);
+    }
+}
+
+pub struct ByteReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    pub fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| invalid_data("unexpected end of data"))?;
+        let slice = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    pub fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn f64(&mut self) -> io::Result<f64> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.position >= self.bytes.len()
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.bytes.len().saturating_sub(self.position)
+    }
+
+    pub fn block(&mut self) -> io::Result<ByteReader<'a>> {
+        let len = self.u32()? as usize;
+        Ok(ByteReader::new(self.take(len)?))
+    }
+}
+
+pub fn compress(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(bytes)?;
+    encoder.finish()
+}
+
+// limit is the most the payload can hold, a stream that inflates past it is rejected before it eats the memory
+pub fn decompress(bytes: &[u8], limit: usize) -> io::Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    ZlibDecoder::new(bytes).take(limit as u64 + 1).read_to_end(&mut decoded)?;
+    if decoded.len() > limit {
+        return Err(invalid_data("compressed data is larger than expected"));
+    }
+    Ok(decoded)
+}
+
+fn check_version(reader: &mut ByteReader, magic: &[u8; 4]) -> io::Result<()> {
+    if reader.take(4)? != magic {
+        return Err(invalid_data("not a world file"));
+    }
+    let major = reader.u16()?;
+    let _minor = reader.u16()?;
+    if major > FORMAT_MAJOR {
+        return Err(invalid_data("world was saved by a newer, incompatible version"));
+    }
+    Ok(())
+}
+
+pub fn write_generation_params(params: &GenerationParams) -> ByteWriter {
+    let mut writer = ByteWriter::default();
+    writer.u8(params.mode as u8);
+    writer.u32(params.seed);
+    writer.u32(params.octaves as u32);
+    writer.f64(params.frequency);
+    writer.u32(params.ground_level);
+    writer.u32(params.water_level);
+    writer.f64(params.noise_multiplier);
+    writer.u32(params.dirt_layer_height);
+    writer.u32(params.bedrock_thickness);
+    writer.u32(params.strata_thickness);
+    writer.f64(params.strata_warp);
+    writer.u8(params.continent_mask as u8);
+    writer.u32(params.continent_cells);
+    writer.f64(params.coast_sharpness);
+    writer.u32(params.ocean_depth);
+    writer.u32(params.cave_worm_count);
+    writer.u32(params.cave_worm_length);
+    writer.f64(params.cave_worm_radius);
+    writer.u32(params.cave_min_y);
+    writer.u32(params.cave_max_y);
+    writer.u32(params.cave_openings);
+    writer.u8(params.structures as u8);
+    writer.f64(params.island_frequency);
+    writer.u32(params.island_height);
+    writer.u32(params.island_thickness);
+    writer.f64(params.island_density);
+    writer
+}
+
+pub fn read_generation_params(reader: &mut ByteReader) -> io::Result<GenerationParams> {
+    Ok(GenerationParams {
+        mode: match reader.u8()? {
+            0 => GenerationMode::Terrain,
+            1 => GenerationMode::FloatingIslands,
+            _ => return Err(invalid_data("unknown generation mode")),
+        },
+        seed: reader.u32()?,
+        octaves: reader.u32()? as usize,
+        frequency: reader.f64()?,
+        ground_level: reader.u32()?,
+        water_level: reader.u32()?,
+        noise_multiplier: reader.f64()?,
+        dirt_layer_height: reader.u32()?,
+        bedrock_thickness: match reader.u32()? {
+            0 => return Err(invalid_data("bedrock thickness must be at least 1")),
+            thickness => thickness,
+        },
+        strata_thickness: match reader.u32()? {
+            0 => return Err(invalid_data("strata thickness must be at least 1")),
+            thickness => thickness,
+        },
+        strata_warp: reader.f64()?,
+        continent_mask: match reader.u8()? {
+            0 => ContinentMask::None,
+            1 => ContinentMask::Voronoi,
+            2 => ContinentMask::Island,
+            _ => return Err(invalid_data("unknown continent mask")),
+        },
+        continent_cells: reader.u32()?,
+        coast_sharpness: reader.f64()?,
+        ocean_depth: reader.u32()?,
+        cave_worm_count: reader.u32()?,
+        cave_worm_length: reader.u32()?,
+        cave_worm_radius: reader.f64()?,
+        cave_min_y: reader.u32()?,
+        cave_max_y: reader.u32()?,
+        cave_openings: reader.u32()?,
+        structures: reader.u8()? != 0,
+        island_frequency: reader.f64()?,
+        island_height: reader.u32()?,
+        island_thickness: reader.u32()?,
+        island_density: reader.f64()?,
+    })
+}
+
+fn region_file_name(region_x: i32, region_z: i32) -> String {
+    format!("r.{}.{}.{}", region_x, region_z, REGION_EXTENSION)
+}
+
+fn chunk_slot(chunk_x: i32, chunk_z: i32) -> ([i32; 2], usize) {
+    let region_file = [chunk_x.div_euclid(REGION_FILE_CHUNKS), chunk_z.div_euclid(REGION_FILE_CHUNKS)];
+    let slot = chunk_x.rem_euclid(REGION_FILE_CHUNKS) * REGION_FILE_CHUNKS + chunk_z.rem_euclid(REGION_FILE_CHUNKS);
+    (region_file, slot as usize)
+}
+
+// the params come before the offset table so edits can be checked against the terrain they were made on
+fn write_region_file(path: &Path, params: &GenerationParams, payloads: &[Option<Vec<u8>>]) -> io::Result<()> {
+    let mut header = ByteWriter::default();
+    header.bytes.extend_from_slice(REGION_MAGIC);
+    header.u16(FORMAT_MAJOR);
+    header.u16(FORMAT_MINOR);
+    header.block(write_generation_params(params));
+    let mut offset = header.bytes.len() + REGION_FILE_SLOTS * 8;
+    for payload in payloads.iter() {
+        let len = payload.as_ref().map_or(0, |payload| payload.len());
+        header.u32(if len > 0 { offset as u32 } else { 0 });
+        header.u32(len as u32);
+        offset += len;
+    }
+    for payload in payloads.iter().flatten() {
+        header.bytes.extend_from_slice(payload);
+    }
+    fs::write(path, header.bytes)
+}
+
+// decompressed payloads of every filled slot
+fn read_region_file(path: &Path, expected_params: &GenerationParams) -> io::Result<Vec<Vec<u8>>> {
+    let bytes = fs::read(path)?;
+    let mut reader = ByteReader::new(&bytes);
+    check_version(&mut reader, REGION_MAGIC)?;
+    let params = read_generation_params(&mut reader.block()?)?;
+    if params != *expected_params {
+        return Err(invalid_data("edits were made with different generation params"));
+    }
+    let mut payloads = Vec::new();
+    for _ in 0..REGION_FILE_SLOTS {
+        let offset = reader.u32()? as usize;
+        let len = reader.u32()? as usize;
+        if len == 0 {
+            continue;
+        }
+        let payload = bytes
+            .get(offset..offset + len)
+            .ok_or_else(|| invalid_data("chunk offset out of bounds"))?;
+        payloads.push(decompress(payload, MAX_CHUNK_EDITS_LEN)?);
+    }
+    Ok(payloads)
+}
+
+fn write_block_edits(writer: &mut ByteWriter, edits: &HashMap<usize, BlockType>) {
+    writer.u32(edits.len() as u32);
+    for (index, block_type) in edits.iter() {
+        writer.u32(*index as u32);
+        writer.u8(block_type.id());
+    }
+}
+
+fn write_fluid_levels(writer: &mut ByteWriter, levels: &HashMap<usize, u8>) {
+    writer.u32(levels.len() as u32);
+    for (index, level) in levels.iter() {
+        writer.u32(*index as u32);
+        writer.u8(*level);
+    }
+}
+
+fn read_block_edits(reader: &mut ByteReader, chunk_position: [i32; 2], edits: &mut EditDelta) -> io::Result<()> {
+    let edit_count = reader.u32()?;
+    for _ in 0..edit_count {
+        let index = reader.u32()? as usize;
+        if index >= CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE {
+            return Err(invalid_data("edit outside of its chunk"));
+        }
+        let block_type = BlockType::from_id(reader.u8()?).ok_or_else(|| invalid_data("unknown block id"))?;
+        edits.record(chunk_position, index, block_type);
+    }
+    Ok(())
+}
+
+fn read_fluid_levels(reader: &mut ByteReader, chunk_position: [i32; 2], edits: &mut EditDelta) -> io::Result<()> {
+    let level_count = reader.u32()?;
+    for _ in 0..level_count {
+        let index = reader.u32()? as usize;
+        if index >= CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE {
+            return Err(invalid_data("fluid level outside of its chunk"));
+        }
+        edits.record_fluid_level(chunk_position, index, reader.u8()?);
+    }
+    Ok(())
+}
+
+// one region file slot: the chunk position, its block edits and its fluid levels
+fn write_chunk_edits(
+    chunk_position: [i32; 2],
+    edits: Option<&HashMap<usize, BlockType>>,
+    levels: Option<&HashMap<usize, u8>>,
+) -> ByteWriter {
+    let mut writer = ByteWriter::default();
+    writer.i32(chunk_position[0]);
+    writer.i32(chunk_position[1]);
+    write_block_edits(&mut writer, edits.unwrap_or(&HashMap::new()));
+    write_fluid_levels(&mut writer, levels.unwrap_or(&HashMap::new()));
+    writer
+}
+
+fn read_chunk_edits(reader: &mut ByteReader, edits: &mut EditDelta) -> io::Result<()> {
+    let chunk_position = [reader.i32()?, reader.i32()?];
+    read_block_edits(reader, chunk_position, edits)?;
+    read_fluid_levels(reader, chunk_position, edits)
+}
+
+// writes world.dat and the region files covering the region, each chunk slot holding that chunk's edits;
+// files of chunks without edits are written too so nothing stale from an earlier save is left behind
+pub fn save_world(directory: &Path, region: &Region) -> io::Result<()> {
+    fs::create_dir_all(directory)?;
+    let mut world = ByteWriter::default();
+    world.bytes.extend_from_slice(WORLD_MAGIC);
+    world.u16(FORMAT_MAJOR);
+    world.u16(FORMAT_MINOR);
+    world.block(write_generation_params(&region.generation_params));
+    fs::write(directory.join(WORLD_FILE), world.bytes)?;
+
+    let block_edits: HashMap<&[i32; 2], &HashMap<usize, BlockType>> = region.edits.chunks().collect();
+    let fluid_levels: HashMap<&[i32; 2], &HashMap<usize, u8>> = region.edits.fluid_levels().collect();
+    let mut region_files: HashMap<[i32; 2], Vec<Option<Vec<u8>>>> = HashMap::new();
+    for chunk in region.chunk_buffer.iter() {
+        let chunk_position = [chunk.world_position[0] as i32, chunk.world_position[2] as i32];
+        let (region_file, slot) = chunk_slot(chunk_position[0], chunk_position[1]);
+        let payloads = region_files
+            .entry(region_file)
+            .or_insert_with(|| vec![None; REGION_FILE_SLOTS]);
+        let edits = block_edits.get(&chunk_position).copied();
+        let levels = fluid_levels.get(&chunk_position).copied();
+        if edits.is_some() || levels.is_some() {
+            payloads[slot] = Some(compress(&write_chunk_edits(chunk_position, edits, levels).bytes)?);
+        }
+    }
+    for (region_file, payloads) in region_files.iter() {
+        write_region_file(
+            &directory.join(region_file_name(region_file[0], region_file[1])),
+            &region.generation_params,
+            payloads,
+        )?;
+    }
+    Ok(())
+}
+
+// regenerates the terrain from the stored params and replays the saved edits on top
+pub fn load_world(directory: &Path, storage_kind: StorageKind) -> io::Result<Region> {
+    let bytes = fs::read(directory.join(WORLD_FILE))?;
+    let mut reader = ByteReader::new(&bytes);
+    check_version(&mut reader, WORLD_MAGIC)?;
+    // the seed is part of the params, which are also what the region files are checked against
+    let params = read_generation_params(&mut reader.block()?)?;
+
+    let mut region = Region::new([0, 0], params);
+    region.set_storage_kind(storage_kind);
+    let mut edits = EditDelta::default();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.extension().and_then(|extension| extension.to_str()) != Some(REGION_EXTENSION) {
+            continue;
+        }
+        for payload in read_region_file(&path, &region.generation_params)? {
+            read_chunk_edits(&mut ByteReader::new(&payload), &mut edits)?;
+        }
+    }
+    edits.replay(&mut region);
+    region.edits = edits;
+    // replayed edits bypass the incremental relight
+    light::light_region(&mut region);
+    Ok(region)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a fresh directory under the system temp dir, removed again by the test that made it
+    fn temp_directory(name: &str) -> std::path::PathBuf {
+        let directory = std::env::temp_dir().join(format!("voxels-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    const EDITED: [([i32; 3], BlockType); 4] = [
+        ([3, 60, 3], BlockType::SLATE),
+        ([-20, 2, -5], BlockType::AIR),
+        ([40, 30, -40], BlockType::GRANITE),
+        ([0, 0, 0], BlockType::SANDSTONE),
+    ];
+
+    #[test]
+    fn zero_layer_thicknesses_are_rejected() {
+        let params = GenerationParams::default();
+        let writer = write_generation_params(&params);
+        assert_eq!(read_generation_params(&mut ByteReader::new(&writer.bytes)).unwrap(), params);
+        for zeroed in [
+            GenerationParams { bedrock_thickness: 0, ..params },
+            GenerationParams { strata_thickness: 0, ..params },
+        ] {
+            let writer = write_generation_params(&zeroed);
+            let error = read_generation_params(&mut ByteReader::new(&writer.bytes)).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn edits_survive_a_save_and_load() {
+        let directory = temp_directory("round-trip");
+        let mut region = Region::new([0, 0], GenerationParams::default());
+        for (position, block_type) in EDITED {
+            assert!(region.set_block(position, block_type));
+        }
+        save_world(&directory, &region).unwrap();
+        let loaded = load_world(&directory, StorageKind::Palette).unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(loaded.generation_params, region.generation_params);
+        for (position, block_type) in EDITED {
+            assert_eq!(loaded.get_block(position), Some(block_type));
+        }
+        // everything else is the regenerated terrain
+        let fresh = Region::new([0, 0], GenerationParams::default());
+        let (min, max) = fresh.bounds();
+        for x in min[0]..=max[0] {
+            for y in min[1]..=max[1] {
+                for z in min[2]..=max[2] {
+                    if EDITED.iter().any(|(position, _)| *position == [x, y, z]) {
+                        continue;
+                    }
+                    assert_eq!(loaded.get_block([x, y, z]), fresh.get_block([x, y, z]), "at {:?}", [x, y, z]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn edits_made_with_other_params_are_rejected() {
+        let directory = temp_directory("mismatch");
+        let other_directory = temp_directory("mismatch-other");
+        let mut region = Region::new([0, 0], GenerationParams::default());
+        for (position, block_type) in EDITED {
+            region.set_block(position, block_type);
+        }
+        save_world(&directory, &region).unwrap();
+        let other = Region::new(
+            [0, 0],
+            GenerationParams {
+                ground_level: 24,
+                structures: false,
+                ..GenerationParams::default()
+            },
+        );
+        save_world(&other_directory, &other).unwrap();
+        // the region files now sit next to a world.dat describing different terrain
+        fs::copy(other_directory.join(WORLD_FILE), directory.join(WORLD_FILE)).unwrap();
+        let error = load_world(&directory, StorageKind::Palette).err().unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        fs::remove_dir_all(&other_directory).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn oversized_streams_are_rejected() {
+        let payload = vec![7; 1000];
+        let compressed = compress(&payload).unwrap();
+        assert_eq!(decompress(&compressed, 1000).unwrap(), payload);
+        let error = decompress(&compressed, 999).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn region_slots_larger_than_a_chunk_of_edits_are_rejected() {
+        let directory = temp_directory("oversized");
+        fs::create_dir_all(&directory).unwrap();
+        let params = GenerationParams::default();
+        let path = directory.join(region_file_name(0, 0));
+        let mut payloads = vec![None; REGION_FILE_SLOTS];
+        payloads[0] = Some(compress(&vec![0; MAX_CHUNK_EDITS_LEN + 1]).unwrap());
+        write_region_file(&path, &params, &payloads).unwrap();
+        let error = read_region_file(&path, &params).err().unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}